@@ -0,0 +1,1056 @@
+use std::{collections::{HashMap, HashSet}, convert::Infallible};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, StatusCode}, middleware::{self, Next},
+    response::{AppendHeaders, IntoResponse, Redirect, Response}, routing::{delete, get, post}, Json, Router
+};
+use chrono::{Days, Local, NaiveDateTime};
+use sea_orm::{
+    sea_query::Table, ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection,
+    EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Schema, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::{audit, backup::backup_db, members, policy::{self, Action}, scheduler, UsrState};
+
+mod api_token;
+mod oidc;
+mod role;
+mod user;
+mod user_session;
+
+pub use api_token::{Model as ApiToken, Scope};
+pub use oidc::{Oidc, OidcConfig};
+pub use role::Role;
+pub use user::Model as User;
+
+/// Name of the cookie that holds the session token
+const SESSION_COOKIE: &str = "usr_session";
+/// Name of the cookie that holds the state of an SSO login that has been started
+const OIDC_STATE_COOKIE: &str = "usr_oidc_state";
+/// Days that a session lasts before the user has to log in again
+const SESSION_DAYS: u64 = 30;
+const MIN_PASSWORD_LEN: usize = 8;
+/// Days that an API token lasts if no expiry is given
+const DEFAULT_TOKEN_DAYS: u64 = 90;
+const MAX_TOKEN_DAYS: u64 = 365;
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Makes the `Set-Cookie` value for a session token, which expires after `max_age` seconds
+fn session_cookie(token: &str, max_age: u64) -> String {
+    // The frontend is on a different site in production, so the cookie has to be sent cross-site
+    #[cfg(not(debug_assertions))]
+    let same_site = "SameSite=None; Secure";
+    #[cfg(debug_assertions)]
+    let same_site = "SameSite=Lax";
+    format!("{SESSION_COOKIE}={token}; Path=/; HttpOnly; Max-Age={max_age}; {same_site}")
+}
+
+/// Makes the `Set-Cookie` value that ties an SSO login to the browser that started it.
+/// The provider redirects back with a top-level GET, which `SameSite=Lax` cookies are sent with.
+fn oidc_state_cookie(state: &str, max_age: u64) -> String {
+    let secure = if cfg!(debug_assertions) { "" } else { "; Secure" };
+    format!("{OIDC_STATE_COOKIE}={state}; Path=/; HttpOnly; Max-Age={max_age}; SameSite=Lax{secure}")
+}
+
+/// Gets the value of a cookie of a request
+fn find_cookie<'a>(headers: &'a HeaderMap, cookie_name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|cookies| cookies.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == cookie_name).then_some(value)
+        })
+}
+
+/// Gets the session token from the cookies of a request
+fn cookie_token(headers: &HeaderMap) -> Option<&str> {
+    find_cookie(headers, SESSION_COOKIE)
+}
+
+/// Gets the token from an `Authorization: Bearer` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Hashes a password on a blocking thread, since argon2 is slow on purpose
+async fn hash_password(password: String) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| anyhow::anyhow!("{e}"))
+    })
+    .await?
+}
+
+/// Hash of a random password that was thrown away, so nothing verifies against it
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$M4giQ/iM/P27/W0KLHZJKQ$ijNY8pOm0zA+6s1C56iH9/dn4BiYG74ejpucz/sx2nQ";
+
+/// Checks a password against a hash. Users without a password, like those from SSO, have no hash,
+/// in which case the dummy hash is checked so that the time does not reveal which users have one.
+async fn verify_password(password: String, password_hash: String) -> bool {
+    tokio::task::spawn_blocking(move || match PasswordHash::new(&password_hash) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => {
+            if let Ok(hash) = PasswordHash::new(DUMMY_PASSWORD_HASH) {
+                let _ = Argon2::default().verify_password(password.as_bytes(), &hash);
+            }
+            false
+        }
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Finds the user that is logged in with a session token, if the session has not expired
+async fn find_session_user(db: &impl ConnectionTrait, token: &str) -> Result<Option<User>, sea_orm::DbErr> {
+    let Some(session) = user_session::Entity::find_by_id(hash_token(token))
+        .filter(user_session::Column::Expires.gt(Local::now().naive_local()))
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    user::Entity::find_by_id(session.user).one(db).await
+}
+
+/// Finds the unexpired API token and the user that issued it, marking the token as used
+async fn find_token_user(db: &impl ConnectionTrait, token: &str) -> Result<Option<(ApiToken, User)>, sea_orm::DbErr> {
+    let now = Local::now().naive_local();
+    let Some(api_token) = api_token::Entity::find()
+        .filter(api_token::Column::TokenHash.eq(hash_token(token)))
+        .filter(api_token::Column::Expires.gt(now))
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(user) = user::Entity::find_by_id(api_token.user).one(db).await? else {
+        return Ok(None);
+    };
+    let api_token = api_token::ActiveModel {
+        id: ActiveValue::Unchanged(api_token.id),
+        last_used: ActiveValue::Set(Some(now)),
+        ..Default::default()
+    }.update(db).await?;
+    Ok(Some((api_token, user)))
+}
+
+/// Whether a request may be made with the session cookie. The cookie is sent cross-site in
+/// production, including with plain HTML forms that need no CORS preflight, so requests that
+/// change something must come from the frontend. Browsers always send an `Origin` with those.
+fn trusted_origin(request: &Request) -> bool {
+    // The cookie is `SameSite=Lax` while debugging, so browsers keep it from cross-site POSTs
+    if cfg!(debug_assertions) || request.method().is_safe() {
+        return true;
+    }
+    request
+        .headers()
+        .get(header::ORIGIN)
+        .is_none_or(|origin| origin == crate::FRONTEND_ORIGIN)
+}
+
+/// Adds the logged in `User` to the extensions of every request with a valid session cookie.
+/// Requests with a bearer token get the user that issued it, along with the `ApiToken` itself.
+pub async fn load_user(State(state): State<&'static UsrState>, mut request: Request, next: Next) -> Response {
+    if let Some(token) = bearer_token(request.headers()) {
+        match find_token_user(&state.db, token).await {
+            Ok(Some((api_token, user))) => {
+                request.extensions_mut().insert(api_token);
+                request.extensions_mut().insert(user);
+            }
+            Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid token").into_response(),
+            Err(e) => {
+                error!("Failed to find token: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        }
+    } else if let Some(token) = cookie_token(request.headers()) {
+        if !trusted_origin(&request) {
+            return (StatusCode::FORBIDDEN, "Untrusted origin").into_response();
+        }
+        match find_session_user(&state.db, token).await {
+            Ok(Some(user)) => {
+                request.extensions_mut().insert(user);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to find session: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        }
+    }
+    next.run(request).await
+}
+
+/// Extracts the logged in user, rejecting requests without one
+impl<S: Send + Sync> FromRequestParts<S> for User {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<User>()
+            .cloned()
+            .ok_or((StatusCode::UNAUTHORIZED, "Not logged in"))
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for User {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<User>().cloned())
+    }
+}
+
+/// Extracts the API token that the request was made with, if any
+impl<S: Send + Sync> OptionalFromRequestParts<S> for ApiToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<ApiToken>().cloned())
+    }
+}
+
+/// Rejects requests made with an API token that lacks `scope`. Requests without a token pass.
+/// This should be added with `route_layer` after every route of a module.
+pub async fn require_scope(State(scope): State<Scope>, request: Request, next: Next) -> Response {
+    if request.extensions().get::<ApiToken>().is_some_and(|api_token| !api_token.grants(scope)) {
+        return (StatusCode::FORBIDDEN, "Token is missing a scope").into_response();
+    }
+    next.run(request).await
+}
+
+/// Rejects requests without a logged in user, or with an API token that lacks `scope`.
+/// This should be added with `route_layer` after the routes that it protects, and relies
+/// on `load_user` running first.
+pub async fn require_user(State(scope): State<Scope>, request: Request, next: Next) -> Response {
+    if request.extensions().get::<User>().is_none() {
+        return (StatusCode::UNAUTHORIZED, "Not logged in").into_response();
+    }
+    require_scope(State(scope), request, next).await
+}
+
+/// Rejects requests without a session, since API tokens cannot manage accounts
+async fn require_session(request: Request, next: Next) -> Response {
+    if request.extensions().get::<ApiToken>().is_some() {
+        return (StatusCode::FORBIDDEN, "Tokens cannot manage accounts").into_response();
+    }
+    if request.extensions().get::<User>().is_none() {
+        return (StatusCode::UNAUTHORIZED, "Not logged in").into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+#[axum::debug_handler]
+async fn login(State(state): State<&'static UsrState>, Json(Login { username, password }): Json<Login>) -> Response {
+    let user = match user::Entity::find()
+        .filter(user::Column::Username.eq(username.trim()))
+        .one(&state.db)
+        .await
+    {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to find user: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let Some(user) = user else {
+        // Take as long as a wrong password would, so that the time does not reveal which usernames exist
+        verify_password(password, String::new()).await;
+        return (StatusCode::UNAUTHORIZED, "Wrong username or password").into_response();
+    };
+    if !verify_password(password, user.password_hash.clone()).await {
+        return (StatusCode::UNAUTHORIZED, "Wrong username or password").into_response();
+    }
+
+    match start_session(&state.db, user.id).await {
+        Ok(cookie) => ([(header::SET_COOKIE, cookie)], Json(user)).into_response(),
+        Err(e) => {
+            error!("Failed to start session: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Creates a session for a user, returning the `Set-Cookie` value for it.
+/// Expired sessions of every user are cleaned up at the same time.
+async fn start_session(db: &impl ConnectionTrait, user: u32) -> Result<String, sea_orm::DbErr> {
+    let now = Local::now().naive_local();
+    user_session::Entity::delete_many()
+        .filter(user_session::Column::Expires.lte(now))
+        .exec(db)
+        .await?;
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    user_session::ActiveModel {
+        token_hash: ActiveValue::Set(hash_token(&token)),
+        user: ActiveValue::Set(user),
+        created: ActiveValue::Set(now),
+        expires: ActiveValue::Set(now + Days::new(SESSION_DAYS)),
+    }.insert(db).await?;
+
+    Ok(session_cookie(&token, SESSION_DAYS * 24 * 60 * 60))
+}
+
+#[axum::debug_handler]
+async fn logout(State(state): State<&'static UsrState>, headers: HeaderMap) -> Response {
+    if let Some(token) = cookie_token(&headers) {
+        if let Err(e) = user_session::Entity::delete_by_id(hash_token(token)).exec(&state.db).await {
+            error!("Failed to end session: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    }
+    ([(header::SET_COOKIE, session_cookie("", 0))], "").into_response()
+}
+
+/// Maps the ids of every user to their usernames
+pub async fn usernames(db: &impl ConnectionTrait) -> Result<HashMap<u32, String>, sea_orm::DbErr> {
+    Ok(user::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.id, model.username))
+        .collect())
+}
+
+/// Gets the roles of a user
+pub async fn roles(db: &impl ConnectionTrait, user: u32) -> Result<Vec<role::Model>, sea_orm::DbErr> {
+    role::Entity::find()
+        .filter(role::Column::User.eq(user))
+        .all(db)
+        .await
+}
+
+#[derive(Serialize)]
+struct RoleInfo {
+    role: Role,
+    /// Name of the team that a lead leads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team: Option<String>,
+}
+
+#[derive(Serialize)]
+struct UserInfo {
+    #[serde(flatten)]
+    user: User,
+    /// Roles on top of being a member, which every user is
+    roles: Vec<RoleInfo>,
+}
+
+impl UserInfo {
+    fn new(user: User, roles: &[role::Model], team_names: &HashMap<String, String>) -> Self {
+        let roles = roles
+            .iter()
+            .filter(|model| model.user == user.id)
+            .map(|model| RoleInfo {
+                role: model.role,
+                team: (model.role == Role::Lead)
+                    .then(|| team_names.get(&model.team).cloned().unwrap_or_else(|| model.team.clone())),
+            })
+            .collect();
+        Self { user, roles }
+    }
+}
+
+/// Gets the user that is logged in
+#[axum::debug_handler]
+async fn get_user(State(state): State<&'static UsrState>, user: User) -> Response {
+    let (roles, team_names) = tokio::join!(
+        roles(&state.db, user.id),
+        scheduler::team_names(&state.db),
+    );
+
+    match (roles, team_names) {
+        (Ok(roles), Ok(team_names)) => Json(UserInfo::new(user, &roles, &team_names)).into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to get user: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn list_users(State(state): State<&'static UsrState>) -> Response {
+    let (users, roles, team_names) = tokio::join!(
+        user::Entity::find().order_by_asc(user::Column::Username).all(&state.db),
+        role::Entity::find().all(&state.db),
+        scheduler::team_names(&state.db),
+    );
+
+    match (users, roles, team_names) {
+        (Ok(users), Ok(roles), Ok(team_names)) => Json(
+            users
+                .into_iter()
+                .map(|user| UserInfo::new(user, &roles, &team_names))
+                .collect::<Vec<_>>(),
+        ).into_response(),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            error!("Failed to list users: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SetRole {
+    username: String,
+    role: Role,
+    /// Code or name of the team, which only leads have
+    #[serde(default)]
+    team: Option<String>,
+}
+
+impl SetRole {
+    async fn check(self, db: &impl ConnectionTrait) -> Result<Result<role::Model, &'static str>, sea_orm::DbErr> {
+        let Some(user) = user::Entity::find()
+            .filter(user::Column::Username.eq(self.username.trim()))
+            .one(db)
+            .await?
+        else {
+            return Ok(Err("User not found"));
+        };
+        let team = match (self.role, self.team) {
+            (Role::Lead, Some(team)) => match scheduler::find_team(db, &team).await? {
+                Some(model) => model.code,
+                None => return Ok(Err("Unknown team")),
+            },
+            (Role::Lead, None) => return Ok(Err("Leads need a team")),
+            (_, Some(_)) => return Ok(Err("Only leads have a team")),
+            (_, None) => String::new(),
+        };
+        Ok(Ok(role::Model {
+            user: user.id,
+            role: self.role,
+            team,
+        }))
+    }
+}
+
+#[axum::debug_handler]
+async fn set_role(State(state): State<&'static UsrState>, user: User, Json(set_role): Json<SetRole>) -> (StatusCode, &'static str) {
+    let model = match set_role.check(&state.db).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg),
+        Err(e) => {
+            error!("Failed to check role: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        if role::Entity::find_by_id((model.user, model.role, model.team.clone())).one(tx).await?.is_some() {
+            return Ok(());
+        }
+        let model = role::ActiveModel::from(model).insert(tx).await?;
+        audit::record(tx, Some(user.id), "set_role", "role", model.user, Value::Null, json!(model)).await
+    })).await;
+
+    match result {
+        Ok(_) => {
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to set role: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn del_role(State(state): State<&'static UsrState>, user: User, Json(set_role): Json<SetRole>) -> (StatusCode, &'static str) {
+    let model = match set_role.check(&state.db).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg),
+        Err(e) => {
+            error!("Failed to check role: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let result = role::Entity::delete_by_id((model.user, model.role, model.team.clone())).exec(tx).await?;
+        if result.rows_affected == 0 {
+            return Ok(false);
+        }
+        audit::record(tx, Some(user.id), "del_role", "role", model.user, json!(model), Value::Null).await?;
+        Ok::<_, sea_orm::DbErr>(true)
+    })).await;
+
+    match result {
+        Ok(false) => (StatusCode::BAD_REQUEST, "User does not have the role"),
+        Ok(true) => {
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to delete role: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+/// Sends the user to the OIDC provider to log in
+#[axum::debug_handler]
+async fn login_oidc(State(state): State<&'static UsrState>) -> Response {
+    let Some(oidc) = &state.oidc else {
+        return (StatusCode::NOT_FOUND, "SSO is not configured").into_response();
+    };
+    match oidc.authorization_url().await {
+        Ok((url, login_state)) => (
+            [(header::SET_COOKIE, oidc_state_cookie(&login_state, oidc::PENDING_TIMEOUT.as_secs()))],
+            Redirect::to(url.as_str()),
+        ).into_response(),
+        Err(e) => {
+            error!("Failed to start SSO login: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+}
+
+/// Finds the user of the member with `uid`, creating one named after the uID if they have none.
+/// Users created this way have no password, so they can only log in through SSO.
+async fn find_or_create_sso_user(db: &DatabaseConnection, uid: u32) -> Result<Result<(User, bool), &'static str>, sea_orm::DbErr> {
+    let Some(member) = members::find_by_uid(db, uid).await? else {
+        return Ok(Err("No member has that uID"));
+    };
+    if let Some(user) = user::Entity::find()
+        .filter(user::Column::Member.eq(member.id))
+        .one(db)
+        .await?
+    {
+        return Ok(Ok((user, false)));
+    }
+    let username = format!("u{uid:07}");
+    if user::Entity::find()
+        .filter(user::Column::Username.eq(&username))
+        .one(db)
+        .await?
+        .is_some()
+    {
+        return Ok(Err("Username is taken"));
+    }
+    let tx = db.begin().await?;
+    let user = user::ActiveModel {
+        id: ActiveValue::NotSet,
+        username: ActiveValue::Set(username),
+        password_hash: ActiveValue::Set(String::new()),
+        member: ActiveValue::Set(Some(member.id)),
+    }.insert(&tx).await?;
+    audit::record(&tx, Some(user.id), "callback_oidc", "user", user.id, Value::Null, json!(user)).await?;
+    tx.commit().await?;
+    Ok(Ok((user, true)))
+}
+
+/// Finishes a login through the OIDC provider, which redirects here with a code
+#[axum::debug_handler]
+async fn callback_oidc(State(state): State<&'static UsrState>, headers: HeaderMap, Query(callback): Query<OidcCallback>) -> Response {
+    let Some(oidc) = &state.oidc else {
+        return (StatusCode::NOT_FOUND, "SSO is not configured").into_response();
+    };
+    let (Some(code), Some(login_state)) = (callback.code, callback.state) else {
+        return (StatusCode::BAD_REQUEST, "Login was not completed").into_response();
+    };
+    // Otherwise an attacker could send someone here with a code of their own, logging them in as the attacker
+    if find_cookie(&headers, OIDC_STATE_COOKIE) != Some(login_state.as_str()) {
+        return (StatusCode::BAD_REQUEST, "Login was started in a different browser").into_response();
+    }
+    let uid = match oidc.finish(&code, &login_state).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to finish SSO login: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let uid = match members::check_uid(&uid) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let user = match find_or_create_sso_user(&state.db, uid).await {
+        Ok(Ok((user, created))) => {
+            if created {
+                backup_db(state);
+            }
+            user
+        }
+        Ok(Err(msg)) => return (StatusCode::FORBIDDEN, msg).into_response(),
+        Err(e) => {
+            error!("Failed to find SSO user: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    match start_session(&state.db, user.id).await {
+        Ok(cookie) => (
+            AppendHeaders([(header::SET_COOKIE, cookie), (header::SET_COOKIE, oidc_state_cookie("", 0))]),
+            Redirect::to(oidc.after_login()),
+        ).into_response(),
+        Err(e) => {
+            error!("Failed to start session: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    username: String,
+    password: String,
+    /// Name of the member that uses the account
+    #[serde(default)]
+    member: Option<String>,
+}
+
+/// Creates a user
+#[axum::debug_handler]
+async fn new_user(State(state): State<&'static UsrState>, user: User, Json(new_user): Json<NewUser>) -> Response {
+    let username = new_user.username.trim().to_string();
+    if username.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Username is empty").into_response();
+    }
+    if new_user.password.chars().count() < MIN_PASSWORD_LEN {
+        return (StatusCode::BAD_REQUEST, "Password must be at least 8 characters").into_response();
+    }
+    let (existing, member) = tokio::join!(
+        user::Entity::find().filter(user::Column::Username.eq(&username)).one(&state.db),
+        async {
+            match &new_user.member {
+                Some(name) => members::find_by_name(&state.db, name).await.map(Some),
+                None => Ok(None),
+            }
+        },
+    );
+    let member = match (existing, member) {
+        (Ok(Some(_)), _) => return (StatusCode::BAD_REQUEST, "Username is taken").into_response(),
+        (Ok(None), Ok(Some(None))) => return (StatusCode::BAD_REQUEST, "Member not found").into_response(),
+        (Ok(None), Ok(member)) => member.flatten().map(|member| member.id),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to check user: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    if let Some(member) = member {
+        match user::Entity::find().filter(user::Column::Member.eq(member)).one(&state.db).await {
+            Ok(Some(_)) => return (StatusCode::BAD_REQUEST, "Member already has a user").into_response(),
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to check user: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        }
+    }
+    let password_hash = match hash_password(new_user.password).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to hash password: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let active_model = user::ActiveModel {
+        id: ActiveValue::NotSet,
+        username: ActiveValue::Set(username),
+        password_hash: ActiveValue::Set(password_hash),
+        member: ActiveValue::Set(member),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.insert(tx).await?;
+        audit::record(tx, Some(user.id), "new_user", "user", model.id, Value::Null, json!(model)).await?;
+        Ok::<_, sea_orm::DbErr>(model)
+    })).await;
+
+    match result {
+        Ok(model) => {
+            backup_db(state);
+            Json(model).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create user: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// The first user, who is created as an admin when there are no users so that they can
+/// create the rest. They should change their password once they log in.
+#[derive(Deserialize)]
+pub struct FirstAdmin {
+    username: String,
+    password: String,
+}
+
+/// Creates the first admin if there are no users yet, returning whether they were created
+pub async fn create_first_admin(db: &DatabaseConnection, admin: FirstAdmin) -> anyhow::Result<bool> {
+    if user::Entity::find().count(db).await? > 0 {
+        return Ok(false);
+    }
+    let username = admin.username.trim().to_string();
+    if username.is_empty() || admin.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(anyhow::anyhow!("The first admin needs a username and a password of at least 8 characters"));
+    }
+    let password_hash = hash_password(admin.password).await?;
+
+    db.transaction(|tx| Box::pin(async move {
+        let model = user::ActiveModel {
+            id: ActiveValue::NotSet,
+            username: ActiveValue::Set(username),
+            password_hash: ActiveValue::Set(password_hash),
+            member: ActiveValue::Set(None),
+        }.insert(tx).await?;
+        audit::record(tx, None, "create_first_admin", "user", model.id, Value::Null, json!(model)).await?;
+        let role = role::ActiveModel {
+            user: ActiveValue::Set(model.id),
+            role: ActiveValue::Set(Role::Admin),
+            team: ActiveValue::Set(String::new()),
+        }.insert(tx).await?;
+        audit::record(tx, None, "create_first_admin", "role", model.id, Value::Null, json!(role)).await
+    })).await?;
+
+    Ok(true)
+}
+
+#[derive(Deserialize)]
+struct ChangePassword {
+    old_password: String,
+    password: String,
+}
+
+/// Changes the password of the user that is logged in, which logs out their other sessions
+#[axum::debug_handler]
+async fn change_password(
+    State(state): State<&'static UsrState>,
+    user: User,
+    headers: HeaderMap,
+    Json(change): Json<ChangePassword>,
+) -> (StatusCode, &'static str) {
+    if !verify_password(change.old_password, user.password_hash.clone()).await {
+        return (StatusCode::BAD_REQUEST, "Wrong password");
+    }
+    if change.password.chars().count() < MIN_PASSWORD_LEN {
+        return (StatusCode::BAD_REQUEST, "Password must be at least 8 characters");
+    }
+    let password_hash = match hash_password(change.password).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to hash password: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let current = cookie_token(&headers).map(hash_token).unwrap_or_default();
+
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        user::ActiveModel {
+            id: ActiveValue::Unchanged(user.id),
+            password_hash: ActiveValue::Set(password_hash),
+            ..Default::default()
+        }.update(tx).await?;
+        user_session::Entity::delete_many()
+            .filter(user_session::Column::User.eq(user.id))
+            .filter(user_session::Column::TokenHash.ne(current))
+            .exec(tx)
+            .await?;
+        // Password hashes are never serialized, so there is nothing to show
+        audit::record(tx, Some(user.id), "change_password", "user", user.id, Value::Null, Value::Null).await
+    })).await;
+
+    match result {
+        Ok(_) => {
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to change password: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewToken {
+    name: String,
+    pub scopes: HashSet<Scope>,
+    /// Days until the token expires
+    #[serde(default)]
+    days: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TokenInfo {
+    id: u32,
+    name: String,
+    /// Username of the user that issued the token
+    user: String,
+    scopes: Vec<Scope>,
+    created: NaiveDateTime,
+    expires: NaiveDateTime,
+    last_used: Option<NaiveDateTime>,
+}
+
+impl TokenInfo {
+    fn new(model: ApiToken, usernames: &HashMap<u32, String>) -> Self {
+        Self {
+            id: model.id,
+            user: usernames.get(&model.user).cloned().unwrap_or_default(),
+            scopes: model.scopes().collect(),
+            name: model.name,
+            created: model.created,
+            expires: model.expires,
+            last_used: model.last_used,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct IssuedToken {
+    /// Only shown this once, as only its hash is stored
+    token: String,
+    #[serde(flatten)]
+    info: TokenInfo,
+}
+
+/// Issues an API token that acts for the user that is logged in, limited to its scopes
+#[axum::debug_handler]
+async fn new_token(State(state): State<&'static UsrState>, user: User, Json(new_token): Json<NewToken>) -> Response {
+    let name = new_token.name.trim().to_string();
+    if name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Name is empty").into_response();
+    }
+    if new_token.scopes.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Token needs a scope").into_response();
+    }
+    let days = new_token.days.unwrap_or(DEFAULT_TOKEN_DAYS);
+    if !(1..=MAX_TOKEN_DAYS).contains(&days) {
+        return (StatusCode::BAD_REQUEST, "Tokens must last between 1 and 365 days").into_response();
+    }
+    let mut scopes: Vec<_> = new_token.scopes.into_iter().map(Scope::as_str).collect();
+    scopes.sort_unstable();
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let now = Local::now().naive_local();
+    let active_model = api_token::ActiveModel {
+        id: ActiveValue::NotSet,
+        name: ActiveValue::Set(name),
+        token_hash: ActiveValue::Set(hash_token(&token)),
+        user: ActiveValue::Set(user.id),
+        scopes: ActiveValue::Set(scopes.join(" ")),
+        created: ActiveValue::Set(now),
+        expires: ActiveValue::Set(now + Days::new(days)),
+        last_used: ActiveValue::Set(None),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.insert(tx).await?;
+        audit::record(tx, Some(user.id), "new_token", "token", model.id, Value::Null, json!(model)).await?;
+        Ok::<_, sea_orm::DbErr>(model)
+    })).await;
+
+    match result {
+        Ok(model) => {
+            backup_db(state);
+            let usernames = HashMap::from([(user.id, user.username)]);
+            Json(IssuedToken { token, info: TokenInfo::new(model, &usernames) }).into_response()
+        }
+        Err(e) => {
+            error!("Failed to issue token: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Lists the tokens of the user that is logged in, or every token for admins
+#[axum::debug_handler]
+async fn list_tokens(State(state): State<&'static UsrState>, user: User) -> Response {
+    let all = match policy::Roles::load(&state.db, user.id).await {
+        Ok(roles) => roles.allows(&Action::ManageOthersTokens),
+        Err(e) => {
+            error!("Failed to load roles: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let mut query = api_token::Entity::find().order_by_asc(api_token::Column::Id);
+    if !all {
+        query = query.filter(api_token::Column::User.eq(user.id));
+    }
+    let (tokens, usernames) = tokio::join!(
+        query.all(&state.db),
+        usernames(&state.db),
+    );
+
+    match (tokens, usernames) {
+        (Ok(tokens), Ok(usernames)) => Json(
+            tokens
+                .into_iter()
+                .map(|model| TokenInfo::new(model, &usernames))
+                .collect::<Vec<_>>(),
+        ).into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to list tokens: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteToken {
+    pub id: u32,
+}
+
+/// Finds the id of the user that issued a token
+pub async fn token_owner(db: &impl ConnectionTrait, id: u32) -> Result<Option<u32>, sea_orm::DbErr> {
+    Ok(api_token::Entity::find_by_id(id).one(db).await?.map(|model| model.user))
+}
+
+/// Revokes a token. Users can revoke their own tokens, and admins can revoke anyone's.
+#[axum::debug_handler]
+async fn del_token(State(state): State<&'static UsrState>, user: User, Json(DeleteToken { id }): Json<DeleteToken>) -> (StatusCode, &'static str) {
+    let model = match api_token::Entity::find_by_id(id).one(&state.db).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Token not found"),
+        Err(e) => {
+            error!("Failed to find token: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        api_token::Entity::delete_by_id(id).exec(tx).await?;
+        audit::record(tx, Some(user.id), "del_token", "token", id, json!(model), Value::Null).await
+    })).await;
+
+    match result {
+        Ok(_) => {
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to revoke token: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+pub fn router() -> Router<&'static UsrState> {
+    Router::new()
+        .route("/change/password", post(change_password))
+        .route("/set/role", post(set_role))
+        .route("/del/role", delete(del_role))
+        .route("/list/user", get(list_users))
+        .route("/new/token", post(new_token))
+        .route("/list/token", get(list_tokens))
+        .route("/del/token", delete(del_token))
+        .route("/new/user", post(new_user))
+        .route_layer(middleware::from_fn(require_session))
+        .route("/login", post(login))
+        .route("/logout", post(logout))
+        .route("/login/oidc", get(login_oidc))
+        .route("/callback/oidc", get(callback_oidc))
+        .route("/get/user", get(get_user))
+}
+
+pub async fn init_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(schema.create_table_from_entity(user::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(user_session::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(role::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(api_token::Entity).if_not_exists()))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(Table::drop().table(user::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(user::Entity)))
+        .await?;
+    db.execute(builder.build(Table::drop().table(user_session::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(user_session::Entity)))
+        .await?;
+    db.execute(builder.build(Table::drop().table(role::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(role::Entity)))
+        .await?;
+    db.execute(builder.build(Table::drop().table(api_token::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(api_token::Entity)))
+        .await?;
+
+    Ok(())
+}
+
+/// Creates a user with roles, returning the `Cookie` header of a session for them.
+/// The user has no password, so they can only be used through the session.
+#[cfg(test)]
+pub async fn test_user(db: &DatabaseConnection, username: &str, roles: &[(Role, &str)]) -> String {
+    let user = user::ActiveModel {
+        id: ActiveValue::NotSet,
+        username: ActiveValue::Set(username.to_string()),
+        password_hash: ActiveValue::Set(String::new()),
+        member: ActiveValue::Set(None),
+    }.insert(db).await.unwrap();
+    for &(role, team) in roles {
+        role::ActiveModel::from(role::Model { user: user.id, role, team: team.to_string() }).insert(db).await.unwrap();
+    }
+    let set_cookie = start_session(db, user.id).await.unwrap();
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn missing_password_hashes() {
+        // The dummy must parse, otherwise no time is spent for users without a hash
+        assert!(PasswordHash::new(DUMMY_PASSWORD_HASH).is_ok());
+        assert!(!verify_password(String::new(), String::new()).await);
+        assert!(!verify_password("password".to_string(), String::new()).await);
+
+        let hash = hash_password("password".to_string()).await.unwrap();
+        assert!(verify_password("password".to_string(), hash.clone()).await);
+        assert!(!verify_password("Password".to_string(), hash).await);
+    }
+}
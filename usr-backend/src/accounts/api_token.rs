@@ -0,0 +1,111 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A token that scripts and kiosks send as a bearer token to act for the user that issued it
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "api_tokens")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub name: String,
+    /// SHA-256 of the token, which is only shown once when it is issued
+    #[sea_orm(unique)]
+    #[serde(skip)]
+    pub token_hash: String,
+    /// Id of a row in `users`
+    pub user: u32,
+    /// Scopes separated by spaces
+    pub scopes: String,
+    pub created: DateTime,
+    pub expires: DateTime,
+    pub last_used: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+    pub fn scopes(&self) -> impl Iterator<Item = Scope> + '_ {
+        self.scopes.split_whitespace().filter_map(Scope::parse)
+    }
+
+    /// Whether the token may be used for `scope`. Writing implies reading.
+    pub fn grants(&self, scope: Scope) -> bool {
+        self.scopes().any(|granted| granted == scope || granted.read() == Some(scope))
+    }
+}
+
+/// What a token may be used for. Tokens can never be used to manage accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "attendance:read")]
+    AttendanceRead,
+    #[serde(rename = "attendance:write")]
+    AttendanceWrite,
+    /// Trusted to check members in without the token from the lab display
+    #[serde(rename = "attendance:kiosk")]
+    AttendanceKiosk,
+    #[serde(rename = "manifest:read")]
+    ManifestRead,
+    #[serde(rename = "manifest:write")]
+    ManifestWrite,
+    #[serde(rename = "members:read")]
+    MembersRead,
+    #[serde(rename = "members:write")]
+    MembersWrite,
+    #[serde(rename = "scheduler:read")]
+    SchedulerRead,
+    #[serde(rename = "scheduler:write")]
+    SchedulerWrite,
+    #[serde(rename = "audit:read")]
+    AuditRead,
+}
+
+impl Scope {
+    const ALL: [Scope; 10] = [
+        Scope::AttendanceRead,
+        Scope::AttendanceWrite,
+        Scope::AttendanceKiosk,
+        Scope::ManifestRead,
+        Scope::ManifestWrite,
+        Scope::MembersRead,
+        Scope::MembersWrite,
+        Scope::SchedulerRead,
+        Scope::SchedulerWrite,
+        Scope::AuditRead,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::AttendanceRead => "attendance:read",
+            Scope::AttendanceWrite => "attendance:write",
+            Scope::AttendanceKiosk => "attendance:kiosk",
+            Scope::ManifestRead => "manifest:read",
+            Scope::ManifestWrite => "manifest:write",
+            Scope::MembersRead => "members:read",
+            Scope::MembersWrite => "members:write",
+            Scope::SchedulerRead => "scheduler:read",
+            Scope::SchedulerWrite => "scheduler:write",
+            Scope::AuditRead => "audit:read",
+        }
+    }
+
+    fn parse(scope: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.as_str() == scope)
+    }
+
+    /// The read scope that comes with a write scope. Kiosks check in through routes that
+    /// need `attendance:read`, so that comes with `attendance:kiosk` too.
+    fn read(self) -> Option<Self> {
+        match self {
+            Scope::AttendanceWrite | Scope::AttendanceKiosk => Some(Scope::AttendanceRead),
+            Scope::ManifestWrite => Some(Scope::ManifestRead),
+            Scope::MembersWrite => Some(Scope::MembersRead),
+            Scope::SchedulerWrite => Some(Scope::SchedulerRead),
+            _ => None,
+        }
+    }
+}
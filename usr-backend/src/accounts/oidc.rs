@@ -0,0 +1,290 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use parking_lot::Mutex;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::OnceCell;
+
+/// How long a user has to log in at the provider before they have to start over
+pub const PENDING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Deserialize)]
+pub struct OidcConfig {
+    /// URL of the provider, which serves `/.well-known/openid-configuration`
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    /// URL of `/api/accounts/callback/oidc` as registered with the provider
+    redirect_uri: String,
+    /// Claim in the ID token that holds the uID of the member
+    #[serde(default = "default_uid_claim")]
+    uid_claim: String,
+    #[serde(default = "default_scopes")]
+    scopes: String,
+    /// Where users are sent once they are logged in
+    #[serde(default = "default_after_login")]
+    after_login: String,
+}
+
+fn default_uid_claim() -> String {
+    "uid".into()
+}
+
+fn default_scopes() -> String {
+    "openid profile".into()
+}
+
+fn default_after_login() -> String {
+    "/".into()
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Logs users in through an OpenID Connect provider with the authorization code flow
+pub struct Oidc {
+    config: OidcConfig,
+    client: Client,
+    /// Fetched on the first login, so that the provider does not have to be up when we start
+    discovery: OnceCell<Discovery>,
+    /// Nonces of logins that have been started, by their state
+    pending: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+            discovery: OnceCell::new(),
+            pending: Mutex::default(),
+        }
+    }
+
+    pub fn after_login(&self) -> &str {
+        &self.config.after_login
+    }
+
+    async fn discovery(&self) -> anyhow::Result<&Discovery> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+                let discovery = self.client.get(url).send().await?.error_for_status()?.json().await?;
+                anyhow::Ok(discovery)
+            })
+            .await
+    }
+
+    /// Starts a login, returning the URL of the provider to send the user to along with the
+    /// state of the login, which the browser has to bring back
+    pub async fn authorization_url(&self) -> anyhow::Result<(Url, String)> {
+        let discovery = self.discovery().await?;
+        let state = hex::encode(rand::random::<[u8; 16]>());
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let url = Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("scope", &self.config.scopes),
+                ("state", &state),
+                ("nonce", &nonce),
+            ],
+        )?;
+
+        let mut pending = self.pending.lock();
+        pending.retain(|_, (_, started)| started.elapsed() < PENDING_TIMEOUT);
+        pending.insert(state.clone(), (nonce, Instant::now()));
+        Ok((url, state))
+    }
+
+    /// Finishes a login by exchanging the code for an ID token, returning the uID claim in it.
+    /// The ID token comes straight from the token endpoint, so TLS stands in for its signature.
+    pub async fn finish(&self, code: &str, state: &str) -> anyhow::Result<Result<String, &'static str>> {
+        let Some((nonce, started)) = self.pending.lock().remove(state) else {
+            return Ok(Err("Login expired"));
+        };
+        if started.elapsed() >= PENDING_TIMEOUT {
+            return Ok(Err("Login expired"));
+        }
+        let discovery = self.discovery().await?;
+        let response: TokenResponse = self
+            .client
+            .post(&discovery.token_endpoint)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_uri),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let payload = response
+            .id_token
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| anyhow::anyhow!("ID token is not a JWT"))?;
+        let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)?;
+
+        if claims["iss"].as_str() != Some(&discovery.issuer) {
+            return Ok(Err("ID token is from the wrong issuer"));
+        }
+        let audience_ok = match &claims["aud"] {
+            Value::String(aud) => *aud == self.config.client_id,
+            Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(&self.config.client_id)),
+            _ => false,
+        };
+        if !audience_ok {
+            return Ok(Err("ID token is for a different client"));
+        }
+        if claims["exp"].as_i64().is_none_or(|exp| exp <= chrono::Utc::now().timestamp()) {
+            return Ok(Err("ID token has expired"));
+        }
+        if claims["nonce"].as_str() != Some(&nonce) {
+            return Ok(Err("ID token is for a different login"));
+        }
+
+        match &claims[&self.config.uid_claim] {
+            Value::String(uid) => Ok(Ok(uid.clone())),
+            // Numbers lose the leading zeros of the uID
+            Value::Number(uid) => match uid.as_u64() {
+                Some(uid) => Ok(Ok(format!("u{uid:07}"))),
+                None => Ok(Err("Account has no uID")),
+            },
+            _ => Ok(Err("Account has no uID")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{extract::State, routing::{get, post}, Form, Json, Router};
+    use serde_json::json;
+
+    use super::*;
+
+    /// Claims that the mock provider puts in the next ID token it issues
+    type Claims = Arc<Mutex<Value>>;
+
+    /// Starts a provider that serves discovery and issues whatever ID token is in `Claims`
+    /// for the code "code", returning its issuer URL
+    async fn mock_provider(claims: Claims) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+        });
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(|| async move { Json(discovery) }))
+            .route(
+                "/token",
+                post(|State(claims): State<Claims>, Form(form): Form<HashMap<String, String>>| async move {
+                    assert_eq!(form["grant_type"], "authorization_code");
+                    assert_eq!(form["code"], "code");
+                    let payload = URL_SAFE_NO_PAD.encode(claims.lock().to_string());
+                    Json(json!({ "id_token": format!("e30.{payload}.c2ln") }))
+                }),
+            )
+            .with_state(claims);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        issuer
+    }
+
+    fn oidc(issuer: &str) -> Oidc {
+        Oidc::new(OidcConfig {
+            issuer: issuer.into(),
+            client_id: "usr".into(),
+            client_secret: "secret".into(),
+            redirect_uri: "http://127.0.0.1/api/accounts/callback/oidc".into(),
+            uid_claim: default_uid_claim(),
+            scopes: default_scopes(),
+            after_login: default_after_login(),
+        })
+    }
+
+    fn valid_claims(issuer: &str, url: &Url) -> Value {
+        let nonce = url.query_pairs().find(|(name, _)| name == "nonce").unwrap().1;
+        json!({
+            "iss": issuer,
+            "aud": "usr",
+            "exp": chrono::Utc::now().timestamp() + 60,
+            "nonce": nonce,
+            "uid": "u1234567",
+        })
+    }
+
+    /// Logs in with claims that the provider issues after `change` is made to valid ones
+    async fn login(change: impl FnOnce(&mut Value)) -> Result<String, &'static str> {
+        let claims = Claims::default();
+        let issuer = mock_provider(claims.clone()).await;
+        let oidc = oidc(&issuer);
+
+        let (url, state) = oidc.authorization_url().await.unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(url.path(), "/authorize");
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], "usr");
+        assert_eq!(params["state"], state);
+
+        let mut valid = valid_claims(&issuer, &url);
+        change(&mut valid);
+        *claims.lock() = valid;
+        oidc.finish("code", &state).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn finishes_login() {
+        assert_eq!(login(|_| {}).await, Ok("u1234567".into()));
+        assert_eq!(login(|claims| claims["aud"] = json!(["other", "usr"])).await, Ok("u1234567".into()));
+        assert_eq!(login(|claims| claims["uid"] = json!(123)).await, Ok("u0000123".into()));
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_claims() {
+        assert_eq!(
+            login(|claims| claims["iss"] = json!("http://127.0.0.1:1")).await,
+            Err("ID token is from the wrong issuer")
+        );
+        assert_eq!(login(|claims| claims["aud"] = json!("other")).await, Err("ID token is for a different client"));
+        assert_eq!(
+            login(|claims| claims["exp"] = json!(chrono::Utc::now().timestamp() - 1)).await,
+            Err("ID token has expired")
+        );
+        assert_eq!(login(|claims| claims["nonce"] = json!("other")).await, Err("ID token is for a different login"));
+        assert_eq!(login(|claims| claims["uid"] = Value::Null).await, Err("Account has no uID"));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_state() {
+        let claims = Claims::default();
+        let issuer = mock_provider(claims.clone()).await;
+        let oidc = oidc(&issuer);
+        let (url, state) = oidc.authorization_url().await.unwrap();
+        *claims.lock() = valid_claims(&issuer, &url);
+
+        assert_eq!(oidc.finish("code", "other").await.unwrap(), Err("Login expired"));
+        assert_eq!(oidc.finish("code", &state).await.unwrap(), Ok("u1234567".into()));
+        // Each login can only be finished once
+        assert_eq!(oidc.finish("code", &state).await.unwrap(), Err("Login expired"));
+    }
+}
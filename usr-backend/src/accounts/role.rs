@@ -0,0 +1,34 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A role given to a user on top of being a member, which every user is
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "user_roles")]
+pub struct Model {
+    /// Id of a row in `users`
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user: u32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub role: Role,
+    /// Code of the team that a lead leads, which is empty for other roles
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub team: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(1))")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[sea_orm(string_value = "A")]
+    Admin,
+    #[sea_orm(string_value = "L")]
+    Lead,
+    #[sea_orm(string_value = "T")]
+    Treasurer,
+}
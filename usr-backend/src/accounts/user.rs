@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "users")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    #[sea_orm(unique)]
+    pub username: String,
+    /// Argon2 hash of the password in the PHC string format
+    #[serde(skip)]
+    pub password_hash: String,
+    /// Id of the row in `members` of the person that uses the account, who has no other account
+    #[sea_orm(unique)]
+    pub member: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+
+/// A user that is logged in through a cookie
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "user_sessions")]
+pub struct Model {
+    /// SHA-256 of the token in the cookie, so that the database alone cannot be used to log in
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub token_hash: String,
+    /// Id of a row in `users`
+    pub user: u32,
+    pub created: DateTime,
+    pub expires: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
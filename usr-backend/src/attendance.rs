@@ -1,53 +1,1893 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, convert::Infallible};
+
 use axum::{
-    extract::State, http::StatusCode, routing::post, Form, Router
+    extract::{Query, State}, http::{header, HeaderMap, StatusCode}, middleware,
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
+    routing::{delete, get, post}, Form, Json, Router
 };
+use futures_util::{stream, Stream};
+use chrono::{Datelike, Days, NaiveTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
 use sea_orm::{
-    sea_query::Table, sqlx::types::chrono::Local, ActiveModelTrait, ActiveValue,
-    ConnectionTrait, DatabaseConnection, Schema,
+    prelude::{Date, DateTime}, sea_query::{OnConflict, Table}, ActiveModelTrait, ActiveValue,
+    ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Schema,
+    TransactionError, TransactionTrait,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::sync::broadcast::error::RecvError;
 use tracing::error;
 
-use crate::{backup::backup_db, UsrState};
+use crate::{accounts::{self, ApiToken, Scope, User}, audit, backup::backup_db, members::{self, check_uid, parse_uid}, migration, scheduler::{self, team_info}, UsrState};
+
+#[allow(clippy::module_inception)]
+mod attendance;
+mod card;
+mod correction;
+mod event;
+mod event_mark;
+mod session;
+mod token;
+
+pub use token::CheckInTokens;
+
+#[derive(Deserialize)]
+struct CheckIn {
+    /// A uID, or whatever a card reader emitted
+    uid: String,
+    /// The token shown on the lab display, which is required unless the client is a kiosk
+    #[serde(default)]
+    token: Option<String>,
+}
+
+/// What a member scanned or typed in at the door
+#[derive(Debug, PartialEq)]
+enum Badge {
+    Uid(u32),
+    Card(String),
+}
+
+/// Parses a uID such as "u1234567" or the output of a card reader. Card readers emit
+/// magstripe tracks such as ";0123456789?" or "%B0123456789^NAME^?", or serials in
+/// decimal or hex, which may be separated like "04:A2:B3:C4"
+fn parse_badge(input: &str) -> Result<Badge, &'static str> {
+    let input = input.trim();
+    if input.starts_with(['u', 'U']) || input.is_empty() {
+        return check_uid(input).map(Badge::Uid);
+    }
+
+    let card = if let Some(track) = input.strip_prefix(';') {
+        // Track 2 is the card number, then an optional field separator and other data
+        track.trim_end_matches('?').split('=').next().unwrap_or_default().to_string()
+    } else if let Some(track) = input.strip_prefix("%B").or_else(|| input.strip_prefix("%b")) {
+        // Track 1 is the card number, then the name and other data separated by carets
+        track.split('^').next().unwrap_or_default().to_string()
+    } else {
+        input
+            .chars()
+            .filter(|c| !matches!(c, ':' | '-' | ' '))
+            .map(|c| c.to_ascii_uppercase())
+            .collect()
+    };
+
+    if card.is_empty() || !card.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Unrecognized card format");
+    }
+    Ok(Badge::Card(card))
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Serialize)]
+struct CheckInResult {
+    uid: u32,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+    direction: Direction,
+    session: session::Model,
+    /// Hours of the session that was just closed by a check-out
+    session_hours: Option<f64>,
+    /// Hours of every session that started today, counting open sessions up to now
+    today_hours: f64,
+    /// Titles of the events that the check-in is attributed to
+    events: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CheckInError {
+    error: &'static str,
+    /// The card that was scanned if it is not registered yet, so that it can be registered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    card: Option<String>,
+}
+
+fn check_in_error(json: bool, error: &'static str, card: Option<String>) -> Response {
+    if json {
+        (StatusCode::BAD_REQUEST, Json(CheckInError { error, card })).into_response()
+    } else {
+        (StatusCode::BAD_REQUEST, error).into_response()
+    }
+}
+
+/// Whether the request came from the lab kiosk or display, which are trusted to be in the lab.
+/// These send either a configured kiosk key or an API token with `attendance:kiosk`.
+fn is_kiosk(state: &'static UsrState, headers: &HeaderMap, api_token: Option<&ApiToken>) -> bool {
+    api_token.is_some_and(|api_token| api_token.grants(Scope::AttendanceKiosk))
+        || headers
+            .get("X-Kiosk-Key")
+            .and_then(|key| key.to_str().ok())
+            .is_some_and(|key| is_kiosk_key(&state.kiosk_keys, key))
+}
+
+/// Whether `key` is one of the configured kiosk keys. The keys are secrets, so they are hashed
+/// to the same length and all of them are compared in constant time.
+fn is_kiosk_key(kiosk_keys: &[String], key: &str) -> bool {
+    let key = Sha256::digest(key.as_bytes());
+    kiosk_keys.iter().fold(false, |found, kiosk_key| {
+        found | bool::from(Sha256::digest(kiosk_key.as_bytes()).as_slice().ct_eq(key.as_slice()))
+    })
+}
+
+/// Whether the client asked for JSON instead of an empty body, such as the lab kiosk
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Checks a member in or out. Clients that accept JSON get back who they are, which way they
+/// went and how long they have been in today, while everyone else gets an empty body.
+///
+/// Clients other than kiosks must send the token from the lab display, so that nobody can
+/// check in from home with just a uID.
+#[axum::debug_handler]
+async fn add_attendance(
+    State(state): State<&'static UsrState>,
+    user: Option<User>,
+    api_token: Option<ApiToken>,
+    headers: HeaderMap,
+    Form(CheckIn { uid, token }): Form<CheckIn>,
+) -> Response {
+    let json = wants_json(&headers);
+    if !is_kiosk(state, &headers, api_token.as_ref()) {
+        let Some(token) = token else {
+            return check_in_error(json, "Missing check-in token", None);
+        };
+        if let Err(msg) = state.check_in_tokens.check(&token, Utc::now()) {
+            return check_in_error(json, msg, None);
+        }
+    }
+    let uid = match parse_badge(&uid) {
+        Ok(Badge::Uid(uid)) => uid,
+        Ok(Badge::Card(card)) => match card::Entity::find_by_id(&card).one(&state.db).await {
+            Ok(Some(model)) => model.uid,
+            Ok(None) => return check_in_error(json, "Card is not registered", Some(card)),
+            Err(e) => {
+                error!("Failed to find card: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        },
+        Err(msg) => return check_in_error(json, msg, None),
+    };
+    let now = now_in(state.time_zone);
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let active_model = attendance::ActiveModel {
+            uid: ActiveValue::Set(uid),
+            date: ActiveValue::Set(now),
+        };
+        let model = active_model.insert(tx).await?;
+        audit::record(tx, user.map(|user| user.id), "add_attendance", "attendance", uid, Value::Null, json!(model)).await?;
+        let session = record_check_in(tx, uid, now).await?;
+        let today_hours = today_hours(tx, uid, now).await?;
+        let name = members::find_by_uid(tx, uid).await?.map(|member| member.name);
+        let events = events_at(tx, now).await?;
+        let occupancy = open_sessions(tx, now.date()).await?.len();
+        Ok::<_, sea_orm::DbErr>((session, today_hours, name, events, occupancy))
+    })).await;
+
+    match result {
+        Ok((session, today_hours, name, events, occupancy)) => {
+            backup_db(state);
+            let (direction, session_hours) = match session.end {
+                Some(end) => (Direction::Out, Some(hours_between(session.start, end))),
+                None => (Direction::In, None),
+            };
+            // Nobody may be listening, which is fine
+            let _ = state.occupancy_events.send(OccupancyEvent {
+                uid,
+                name: name.clone(),
+                direction,
+                time: now,
+                auto_closed: false,
+                occupancy,
+            });
+            if !json {
+                return (StatusCode::OK, "").into_response();
+            }
+            Json(CheckInResult {
+                uid,
+                name,
+                direction,
+                session,
+                session_hours,
+                today_hours,
+                events: events.into_iter().map(|event| event.title).collect(),
+            }).into_response()
+        }
+        Err(e) => {
+            error!("Failed to add attendance: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// The current time in the organization's time zone, which check-ins are recorded in
+fn now_in(time_zone: Tz) -> DateTime {
+    Utc::now().with_timezone(&time_zone).naive_local()
+}
+
+/// Sleeps until a time in the organization's time zone
+async fn sleep_until(time_zone: Tz, time: DateTime) {
+    // A few time zones skip midnight when clocks go forward, so wait until the hour after instead
+    let Some(time) = time_zone
+        .from_local_datetime(&time)
+        .earliest()
+        .or_else(|| time_zone.from_local_datetime(&(time + TimeDelta::hours(1))).earliest())
+    else {
+        return;
+    };
+    tokio::time::sleep((time.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default()).await;
+}
+
+fn hours_between(start: DateTime, end: DateTime) -> f64 {
+    (end - start).num_seconds() as f64 / 3600.0
+}
+
+/// Totals the hours of the sessions of `uid` that started on the day of `now`
+async fn today_hours(db: &impl ConnectionTrait, uid: u32, now: DateTime) -> Result<f64, sea_orm::DbErr> {
+    let sessions = session::Entity::find()
+        .filter(session::Column::Uid.eq(uid))
+        .filter(session::Column::Start.gte(now.date().and_time(NaiveTime::MIN)))
+        .filter(session::Column::AutoClosed.eq(false))
+        .all(db)
+        .await?;
+
+    Ok(sessions
+        .into_iter()
+        .map(|session| hours_between(session.start, session.end.unwrap_or(now)))
+        .sum())
+}
+
+#[derive(Serialize)]
+struct CheckInToken {
+    token: String,
+    /// When the display should show the next token
+    expires: chrono::DateTime<Utc>,
+}
+
+/// Gets the current check-in token for the lab display to show as a QR code
+#[axum::debug_handler]
+async fn get_token(State(state): State<&'static UsrState>, api_token: Option<ApiToken>, headers: HeaderMap) -> Response {
+    if !is_kiosk(state, &headers, api_token.as_ref()) {
+        return (StatusCode::FORBIDDEN, "Not a kiosk").into_response();
+    }
+    let (token, expires) = state.check_in_tokens.current(Utc::now());
+    Json(CheckInToken { token, expires }).into_response()
+}
+
+/// Opens a session for `uid`, or closes the one they have open, returning the session
+async fn record_check_in(db: &impl ConnectionTrait, uid: u32, now: DateTime) -> Result<session::Model, sea_orm::DbErr> {
+    close_stale_sessions(db, now.date(), Some(uid)).await?;
+    let open = session::Entity::find()
+        .filter(session::Column::Uid.eq(uid))
+        .filter(session::Column::End.is_null())
+        .one(db)
+        .await?;
+
+    match open {
+        Some(model) => {
+            let mut active_model: session::ActiveModel = model.into();
+            active_model.end = ActiveValue::Set(Some(now));
+            active_model.update(db).await
+        }
+        None => session::ActiveModel {
+            id: ActiveValue::NotSet,
+            uid: ActiveValue::Set(uid),
+            start: ActiveValue::Set(now),
+            end: ActiveValue::Set(None),
+            auto_closed: ActiveValue::Set(false),
+        }.insert(db).await,
+    }
+}
+
+/// Pairs check-ins up into sessions from scratch, for either one member or everyone.
+/// Sessions that started before `today` are closed if they were left open.
+async fn replay_sessions(db: &impl ConnectionTrait, today: Date, uid: Option<u32>) -> Result<(), sea_orm::DbErr> {
+    let mut delete = session::Entity::delete_many();
+    let mut find = attendance::Entity::find().order_by_asc(attendance::Column::Date);
+    if let Some(uid) = uid {
+        delete = delete.filter(session::Column::Uid.eq(uid));
+        find = find.filter(attendance::Column::Uid.eq(uid));
+    }
+    delete.exec(db).await?;
+
+    for check_in in find.all(db).await? {
+        record_check_in(db, check_in.uid, check_in.date).await?;
+    }
+    close_stale_sessions(db, today, uid).await?;
+    Ok(())
+}
+
+/// Closes sessions that were left open since before `today` at the midnight after they started,
+/// returning the sessions that were closed
+async fn close_stale_sessions(db: &impl ConnectionTrait, today: Date, uid: Option<u32>) -> Result<Vec<session::Model>, sea_orm::DbErr> {
+    let mut query = session::Entity::find()
+        .filter(session::Column::End.is_null())
+        .filter(session::Column::Start.lt(today.and_time(NaiveTime::MIN)));
+    if let Some(uid) = uid {
+        query = query.filter(session::Column::Uid.eq(uid));
+    }
+    let mut closed = vec![];
+
+    for model in query.all(db).await? {
+        let active_model = session::ActiveModel {
+            id: ActiveValue::Unchanged(model.id),
+            end: ActiveValue::Set(Some((model.start.date() + Days::new(1)).and_time(NaiveTime::MIN))),
+            auto_closed: ActiveValue::Set(true),
+            ..Default::default()
+        };
+        closed.push(active_model.update(db).await?);
+    }
+
+    Ok(closed)
+}
+
+/// Closes sessions that were left open overnight shortly after every midnight
+pub fn spawn_session_closer(state: &'static UsrState) {
+    tokio::spawn(async move {
+        loop {
+            let midnight = (now_in(state.time_zone).date() + Days::new(1)).and_time(NaiveTime::MIN);
+            sleep_until(state.time_zone, midnight).await;
+
+            let closed = match close_stale_sessions(&state.db, now_in(state.time_zone).date(), None).await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("Failed to close sessions: {e}");
+                    continue;
+                }
+            };
+            if closed.is_empty() {
+                continue;
+            }
+            backup_db(state);
+
+            let names = members::by_uid(&state.db).await.unwrap_or_default();
+            for session in closed {
+                let _ = state.occupancy_events.send(OccupancyEvent {
+                    uid: session.uid,
+                    name: names.get(&session.uid).map(|member| member.name.clone()),
+                    direction: Direction::Out,
+                    time: session.end.unwrap_or(session.start),
+                    auto_closed: true,
+                    occupancy: 0,
+                });
+            }
+        }
+    });
+}
+
+/// Finds the sessions of members that are in the lab right now, ignoring sessions
+/// from before `today` that have yet to be closed
+async fn open_sessions(db: &impl ConnectionTrait, today: Date) -> Result<Vec<session::Model>, sea_orm::DbErr> {
+    session::Entity::find()
+        .filter(session::Column::End.is_null())
+        .filter(session::Column::Start.gte(today.and_time(NaiveTime::MIN)))
+        .order_by_asc(session::Column::Start)
+        .all(db)
+        .await
+}
+
+#[derive(Serialize, Clone)]
+pub struct OccupancyEvent {
+    uid: u32,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+    direction: Direction,
+    time: DateTime,
+    /// Whether the member forgot to check out, so they were checked out at midnight
+    auto_closed: bool,
+    /// Members in the lab after the event
+    occupancy: usize,
+}
+
+#[derive(Serialize)]
+struct Occupant {
+    uid: u32,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+    /// When the member checked in
+    since: DateTime,
+}
+
+/// Lists who is in the lab right now, from the earliest check-in to the latest
+#[axum::debug_handler]
+async fn get_occupancy(State(state): State<&'static UsrState>) -> Response {
+    let (sessions, members) = tokio::join!(
+        open_sessions(&state.db, now_in(state.time_zone).date()),
+        members::by_uid(&state.db),
+    );
+
+    match (sessions, members) {
+        (Ok(sessions), Ok(members)) => Json(
+            sessions
+                .into_iter()
+                .map(|session| Occupant {
+                    uid: session.uid,
+                    name: members.get(&session.uid).map(|member| member.name.clone()),
+                    since: session.start,
+                })
+                .collect::<Vec<_>>(),
+        ).into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to get occupancy: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Streams check-ins and check-outs as Server-Sent Events named "in" and "out". A "lagged"
+/// event means that some were missed, so the client should get the occupancy again.
+#[axum::debug_handler]
+async fn stream_occupancy(State(state): State<&'static UsrState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = state.occupancy_events.subscribe();
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(event) => {
+                let name = match event.direction {
+                    Direction::In => "in",
+                    Direction::Out => "out",
+                };
+                Event::default()
+                    .event(name)
+                    .data(serde_json::to_string(&event).unwrap_or_default())
+            }
+            Err(RecvError::Lagged(_)) => Event::default().event("lagged").data(""),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), receiver))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[derive(Deserialize)]
+struct AttendanceQuery {
+    /// Only includes check-ins of this uID
+    uid: Option<String>,
+    /// First day to include
+    from: Option<Date>,
+    /// Last day to include
+    to: Option<Date>,
+    /// Page to return, counting from 0. Only used when listing
+    #[serde(default)]
+    page: u64,
+    /// Only used when listing
+    page_size: Option<u64>,
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 500;
+
+impl AttendanceQuery {
+    /// Filters rows by their `uid_column` and the day of their `date_column`
+    fn condition(&self, uid_column: impl ColumnTrait, date_column: impl ColumnTrait) -> Result<Condition, &'static str> {
+        let mut condition = Condition::all();
+        if let Some(uid) = &self.uid {
+            let uid = parse_uid(uid).ok_or("Invalid uID")?;
+            condition = condition.add(uid_column.eq(uid));
+        }
+        if let Some(from) = self.from {
+            condition = condition.add(date_column.gte(from.and_time(NaiveTime::MIN)));
+        }
+        if let Some(to) = self.to {
+            let end = to.checked_add_days(Days::new(1)).ok_or("Invalid date")?;
+            condition = condition.add(date_column.lt(end.and_time(NaiveTime::MIN)));
+        }
+        Ok(condition)
+    }
+
+    fn page_size(&self) -> Result<u64, &'static str> {
+        let page_size = self.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        if page_size == 0 || page_size > MAX_PAGE_SIZE {
+            return Err("Invalid page size");
+        }
+        Ok(page_size)
+    }
+}
+
+#[derive(Serialize)]
+struct CheckInInfo {
+    #[serde(flatten)]
+    check_in: attendance::Model,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AttendancePage {
+    page: u64,
+    page_size: u64,
+    /// Number of check-ins across every page
+    total: u64,
+    check_ins: Vec<CheckInInfo>,
+}
+
+/// Lists check-ins from newest to oldest
+#[axum::debug_handler]
+async fn list_attendance(State(state): State<&'static UsrState>, Query(query): Query<AttendanceQuery>) -> Response {
+    let (condition, page_size) = match (query.condition(attendance::Column::Uid, attendance::Column::Date), query.page_size()) {
+        (Ok(condition), Ok(page_size)) => (condition, page_size),
+        (Err(msg), _) | (_, Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let paginator = attendance::Entity::find()
+        .filter(condition)
+        .order_by_desc(attendance::Column::Date)
+        .order_by_asc(attendance::Column::Uid)
+        .paginate(&state.db, page_size);
+    let (total, check_ins, members) = tokio::join!(
+        paginator.num_items(),
+        paginator.fetch_page(query.page),
+        members::by_uid(&state.db),
+    );
+
+    match (total, check_ins, members) {
+        (Ok(total), Ok(check_ins), Ok(members)) => Json(AttendancePage {
+            page: query.page,
+            page_size,
+            total,
+            check_ins: check_ins
+                .into_iter()
+                .map(|check_in| CheckInInfo {
+                    name: members.get(&check_in.uid).map(|member| member.name.clone()),
+                    check_in,
+                })
+                .collect(),
+        }).into_response(),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            error!("Failed to list attendance: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Replaces the check-in `old` with `new` and records why. Either can be `None` to
+/// add or delete a check-in. Sessions of the affected members are paired up again,
+/// so a check-out added for a forgotten session counts towards hours.
+async fn correct_attendance(
+    state: &'static UsrState,
+    (actor, action): (&User, &'static str),
+    old: Option<attendance::Model>,
+    new: Option<attendance::Model>,
+    reason: String,
+) -> Result<Result<correction::Model, &'static str>, TransactionError<sea_orm::DbErr>> {
+    let reason = reason.trim().to_string();
+    if reason.is_empty() {
+        return Ok(Err("Reason is empty"));
+    }
+    let now = now_in(state.time_zone);
+    let actor = actor.id;
+
+    state.db.transaction(|tx| Box::pin(async move {
+        if let Some(old) = &old {
+            if attendance::Entity::find_by_id((old.uid, old.date)).one(tx).await?.is_none() {
+                return Ok(Err("Check-in not found"));
+            }
+        }
+        if let Some(new) = &new {
+            if attendance::Entity::find_by_id((new.uid, new.date)).one(tx).await?.is_some() {
+                return Ok(Err("Check-in already exists"));
+            }
+        }
+
+        if let Some(old) = &old {
+            attendance::Entity::delete_by_id((old.uid, old.date)).exec(tx).await?;
+        }
+        if let Some(new) = &new {
+            attendance::ActiveModel::from(new.clone()).insert(tx).await?;
+        }
+        let model = correction::ActiveModel {
+            id: ActiveValue::NotSet,
+            old_uid: ActiveValue::Set(old.as_ref().map(|old| old.uid)),
+            old_date: ActiveValue::Set(old.as_ref().map(|old| old.date)),
+            new_uid: ActiveValue::Set(new.as_ref().map(|new| new.uid)),
+            new_date: ActiveValue::Set(new.as_ref().map(|new| new.date)),
+            reason: ActiveValue::Set(reason),
+            corrected: ActiveValue::Set(now),
+        }.insert(tx).await?;
+        if let Some(check_in) = old.as_ref().or(new.as_ref()) {
+            audit::record(tx, Some(actor), action, "attendance", check_in.uid, json!(old), json!(new)).await?;
+        }
+
+        let uids: BTreeSet<u32> = old.iter().chain(new.iter()).map(|check_in| check_in.uid).collect();
+        for uid in uids {
+            replay_sessions(tx, now.date(), Some(uid)).await?;
+        }
+        Ok::<_, sea_orm::DbErr>(Ok(model))
+    })).await
+}
+
+fn correction_response(state: &'static UsrState, result: Result<Result<correction::Model, &'static str>, TransactionError<sea_orm::DbErr>>) -> Response {
+    match result {
+        Ok(Ok(model)) => {
+            backup_db(state);
+            Json(model).into_response()
+        }
+        Ok(Err(msg)) => (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to correct attendance: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PendingCheckIn {
+    uid: String,
+    date: DateTime,
+}
+
+impl PendingCheckIn {
+    fn check(&self) -> Result<attendance::Model, &'static str> {
+        Ok(attendance::Model {
+            uid: check_uid(&self.uid)?,
+            date: self.date,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct NewAttendance {
+    #[serde(flatten)]
+    check_in: PendingCheckIn,
+    reason: String,
+}
+
+/// Adds a check-in by hand, such as a check-out that somebody forgot
+#[axum::debug_handler]
+async fn new_attendance(State(state): State<&'static UsrState>, user: User, Json(NewAttendance { check_in, reason }): Json<NewAttendance>) -> Response {
+    let new = match check_in.check() {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    correction_response(state, correct_attendance(state, (&user, "new_attendance"), None, Some(new), reason).await)
+}
+
+#[derive(Deserialize)]
+struct ChangeAttendance {
+    #[serde(flatten)]
+    check_in: PendingCheckIn,
+    new_uid: String,
+    new_date: DateTime,
+    reason: String,
+}
+
+/// Moves a check-in to a different uID or time, such as when somebody typed the wrong uID
+#[axum::debug_handler]
+async fn change_attendance(State(state): State<&'static UsrState>, user: User, Json(change): Json<ChangeAttendance>) -> Response {
+    let new = PendingCheckIn {
+        uid: change.new_uid,
+        date: change.new_date,
+    };
+    let (old, new) = match (change.check_in.check(), new.check()) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(msg), _) | (_, Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if old == new {
+        return (StatusCode::BAD_REQUEST, "Check-in is unchanged").into_response();
+    }
+    correction_response(state, correct_attendance(state, (&user, "change_attendance"), Some(old), Some(new), change.reason).await)
+}
+
+#[axum::debug_handler]
+async fn del_attendance(State(state): State<&'static UsrState>, user: User, Json(NewAttendance { check_in, reason }): Json<NewAttendance>) -> Response {
+    let old = match check_in.check() {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    correction_response(state, correct_attendance(state, (&user, "del_attendance"), Some(old), None, reason).await)
+}
+
+#[derive(Deserialize)]
+struct CorrectionQuery {
+    /// Only lists corrections to check-ins of this uID, before or after the correction
+    #[serde(default)]
+    uid: Option<String>,
+}
+
+/// Lists corrections from newest to oldest
+#[axum::debug_handler]
+async fn list_corrections(State(state): State<&'static UsrState>, Query(query): Query<CorrectionQuery>) -> Response {
+    let mut select = correction::Entity::find().order_by_desc(correction::Column::Id);
+    if let Some(uid) = &query.uid {
+        let Some(uid) = parse_uid(uid) else {
+            return (StatusCode::BAD_REQUEST, "Invalid uID").into_response();
+        };
+        select = select.filter(
+            Condition::any()
+                .add(correction::Column::OldUid.eq(uid))
+                .add(correction::Column::NewUid.eq(uid)),
+        );
+    }
+
+    match select.all(&state.db).await {
+        Ok(corrections) => Json(corrections).into_response(),
+        Err(e) => {
+            error!("Failed to list corrections: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Quotes a CSV field if it contains anything that would break the row
+fn csv_field(field: &str) -> String {
+    // Spreadsheets run cells that start like a formula, so those are kept as text
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Exports check-ins from oldest to newest as CSV, filtered like `list_attendance` but without pages
+#[axum::debug_handler]
+async fn export_attendance(State(state): State<&'static UsrState>, Query(query): Query<AttendanceQuery>) -> Response {
+    let condition = match query.condition(attendance::Column::Uid, attendance::Column::Date) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let (check_ins, members) = tokio::join!(
+        attendance::Entity::find()
+            .filter(condition)
+            .order_by_asc(attendance::Column::Date)
+            .order_by_asc(attendance::Column::Uid)
+            .all(&state.db),
+        members::by_uid(&state.db),
+    );
+    let (check_ins, members) = match (check_ins, members) {
+        (Ok(check_ins), Ok(members)) => (check_ins, members),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to export attendance: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut csv = String::from("uid,name,date,time\r\n");
+    for check_in in check_ins {
+        let name = members.get(&check_in.uid).map(|member| member.name.as_str()).unwrap_or_default();
+        csv.push_str(&format!(
+            "u{:07},{},{},{}\r\n",
+            check_in.uid,
+            csv_field(name),
+            check_in.date.format("%Y-%m-%d"),
+            check_in.date.format("%H:%M:%S"),
+        ));
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"attendance.csv\""),
+        ],
+        csv,
+    ).into_response()
+}
+
+#[derive(Serialize)]
+struct DailyAttendance {
+    date: Date,
+    check_ins: u32,
+    /// Number of different people that checked in
+    members: u32,
+}
+
+/// Counts check-ins on each day that has any
+#[axum::debug_handler]
+async fn get_daily_attendance(State(state): State<&'static UsrState>, Query(query): Query<AttendanceQuery>) -> Response {
+    let condition = match query.condition(attendance::Column::Uid, attendance::Column::Date) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let check_ins = match attendance::Entity::find().filter(condition).all(&state.db).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to get attendance: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut days = BTreeMap::<Date, (u32, BTreeSet<u32>)>::new();
+    for check_in in check_ins {
+        let (count, members) = days.entry(check_in.date.date()).or_default();
+        *count += 1;
+        members.insert(check_in.uid);
+    }
+
+    Json(
+        days.into_iter()
+            .map(|(date, (check_ins, members))| DailyAttendance {
+                date,
+                check_ins,
+                members: members.len() as u32,
+            })
+            .collect::<Vec<_>>(),
+    ).into_response()
+}
+
+#[derive(Serialize)]
+struct MemberAttendance {
+    uid: u32,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+    check_ins: u32,
+    /// Number of different days checked in on
+    days: u32,
+    first: DateTime,
+    last: DateTime,
+}
+
+/// Counts check-ins of each person that has any
+#[axum::debug_handler]
+async fn get_member_attendance(State(state): State<&'static UsrState>, Query(query): Query<AttendanceQuery>) -> Response {
+    let condition = match query.condition(attendance::Column::Uid, attendance::Column::Date) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let (check_ins, uid_members) = tokio::join!(
+        attendance::Entity::find()
+            .filter(condition)
+            .order_by_asc(attendance::Column::Date)
+            .all(&state.db),
+        members::by_uid(&state.db),
+    );
+    let (check_ins, mut uid_members) = match (check_ins, uid_members) {
+        (Ok(check_ins), Ok(uid_members)) => (check_ins, uid_members),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to get attendance: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut members = BTreeMap::<u32, (MemberAttendance, BTreeSet<Date>)>::new();
+    for check_in in check_ins {
+        let (member, days) = members.entry(check_in.uid).or_insert_with(|| (
+            MemberAttendance {
+                uid: check_in.uid,
+                name: uid_members.remove(&check_in.uid).map(|member| member.name),
+                check_ins: 0,
+                days: 0,
+                first: check_in.date,
+                last: check_in.date,
+            },
+            BTreeSet::new(),
+        ));
+        member.check_ins += 1;
+        member.last = check_in.date;
+        days.insert(check_in.date.date());
+    }
+
+    Json(
+        members
+            .into_values()
+            .map(|(mut member, days)| {
+                member.days = days.len() as u32;
+                member
+            })
+            .collect::<Vec<_>>(),
+    ).into_response()
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    #[serde(flatten)]
+    session: session::Model,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SessionPage {
+    page: u64,
+    page_size: u64,
+    /// Number of sessions across every page
+    total: u64,
+    sessions: Vec<SessionInfo>,
+}
+
+/// Lists sessions from the newest start to the oldest, filtered by the day they started on
+#[axum::debug_handler]
+async fn list_sessions(State(state): State<&'static UsrState>, Query(query): Query<AttendanceQuery>) -> Response {
+    let (condition, page_size) = match (query.condition(session::Column::Uid, session::Column::Start), query.page_size()) {
+        (Ok(condition), Ok(page_size)) => (condition, page_size),
+        (Err(msg), _) | (_, Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let paginator = session::Entity::find()
+        .filter(condition)
+        .order_by_desc(session::Column::Start)
+        .paginate(&state.db, page_size);
+    let (total, sessions, members) = tokio::join!(
+        paginator.num_items(),
+        paginator.fetch_page(query.page),
+        members::by_uid(&state.db),
+    );
+
+    match (total, sessions, members) {
+        (Ok(total), Ok(sessions), Ok(members)) => Json(SessionPage {
+            page: query.page,
+            page_size,
+            total,
+            sessions: sessions
+                .into_iter()
+                .map(|session| SessionInfo {
+                    name: members.get(&session.uid).map(|member| member.name.clone()),
+                    session,
+                })
+                .collect(),
+        }).into_response(),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            error!("Failed to list sessions: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MemberHours {
+    uid: u32,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+    /// Hours in each week, by the Monday that the week starts on
+    weeks: BTreeMap<Date, f64>,
+    total: f64,
+    /// Sessions that were left open overnight, which do not count towards hours
+    auto_closed: u32,
+}
+
+#[derive(Serialize)]
+struct Hours {
+    members: Vec<MemberHours>,
+    /// Hours of the members of each team in each week, where teams are from the current term
+    teams: BTreeMap<String, BTreeMap<Date, f64>>,
+}
+
+/// Totals the hours of closed sessions, filtered by the day they started on
+#[axum::debug_handler]
+async fn get_hours(State(state): State<&'static UsrState>, Query(query): Query<AttendanceQuery>) -> Response {
+    let condition = match query.condition(session::Column::Uid, session::Column::Start) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let (sessions, uid_members, member_teams) = tokio::join!(
+        session::Entity::find()
+            .filter(condition)
+            .filter(session::Column::End.is_not_null())
+            .all(&state.db),
+        members::by_uid(&state.db),
+        scheduler::member_teams(&state.db, state.time_zone),
+    );
+    let (sessions, uid_members, member_teams) = match (sessions, uid_members, member_teams) {
+        (Ok(sessions), Ok(uid_members), Ok(member_teams)) => (sessions, uid_members, member_teams),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            error!("Failed to get hours: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut members = BTreeMap::<u32, MemberHours>::new();
+    let mut teams = BTreeMap::<String, BTreeMap<Date, f64>>::new();
+    for session in sessions {
+        let member = members.entry(session.uid).or_insert_with(|| MemberHours {
+            uid: session.uid,
+            name: uid_members.get(&session.uid).map(|member| member.name.clone()),
+            weeks: BTreeMap::new(),
+            total: 0.0,
+            auto_closed: 0,
+        });
+        if session.auto_closed {
+            member.auto_closed += 1;
+            continue;
+        }
+        let Some(end) = session.end else {
+            continue;
+        };
+        let hours = hours_between(session.start, end);
+        let date = session.start.date();
+        let week = week_of(date);
+        *member.weeks.entry(week).or_default() += hours;
+        member.total += hours;
+
+        let member_teams = uid_members
+            .get(&session.uid)
+            .and_then(|member| member_teams.get(&member.id));
+        for team in member_teams.into_iter().flatten() {
+            *teams.entry(team.clone()).or_default().entry(week).or_default() += hours;
+        }
+    }
+
+    Json(Hours {
+        members: members.into_values().collect(),
+        teams,
+    }).into_response()
+}
+
+#[derive(Deserialize)]
+struct CardRegistration {
+    /// Whatever the card reader emitted
+    card: String,
+    uid: String,
+}
+
+/// Registers a card to a uID, replacing whoever it was registered to before.
+/// This is done at the kiosk when an unregistered card is scanned, or by a logged in user.
+#[axum::debug_handler]
+async fn register_card(
+    State(state): State<&'static UsrState>,
+    user: Option<User>,
+    api_token: Option<ApiToken>,
+    headers: HeaderMap,
+    Json(CardRegistration { card, uid }): Json<CardRegistration>,
+) -> Response {
+    // Tokens without attendance:kiosk are not enough, even though they come with a user
+    let logged_in = user.is_some() && api_token.is_none();
+    if !logged_in && !is_kiosk(state, &headers, api_token.as_ref()) {
+        return (StatusCode::UNAUTHORIZED, "Not logged in").into_response();
+    }
+    let card = match parse_badge(&card) {
+        Ok(Badge::Card(card)) => card,
+        Ok(Badge::Uid(_)) => return (StatusCode::BAD_REQUEST, "Card is a uID").into_response(),
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let uid = match check_uid(&uid) {
+        Ok(uid) => uid,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let model = card::Model {
+        card,
+        uid,
+        registered: now_in(state.time_zone),
+    };
+    let actor = user.map(|user| user.id);
+    let after = model.clone();
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let before = card::Entity::find_by_id(&after.card).one(tx).await?;
+        card::Entity::insert(card::ActiveModel::from(after.clone()))
+            .on_conflict(
+                OnConflict::column(card::Column::Card)
+                    .update_columns([card::Column::Uid, card::Column::Registered])
+                    .to_owned(),
+            )
+            .exec(tx)
+            .await?;
+        audit::record(tx, actor, "register_card", "card", &after.card, json!(before), json!(after)).await
+    })).await;
+
+    match result {
+        Ok(_) => {
+            backup_db(state);
+            Json(model).into_response()
+        }
+        Err(e) => {
+            error!("Failed to register card: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CardQuery {
+    #[serde(default)]
+    uid: Option<String>,
+}
+
+#[axum::debug_handler]
+async fn list_cards(State(state): State<&'static UsrState>, Query(query): Query<CardQuery>) -> Response {
+    let mut select = card::Entity::find().order_by_asc(card::Column::Uid);
+    if let Some(uid) = &query.uid {
+        let Some(uid) = parse_uid(uid) else {
+            return (StatusCode::BAD_REQUEST, "Invalid uID").into_response();
+        };
+        select = select.filter(card::Column::Uid.eq(uid));
+    }
+
+    match select.all(&state.db).await {
+        Ok(cards) => Json(cards).into_response(),
+        Err(e) => {
+            error!("Failed to list cards: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteCard {
+    card: String,
+}
 
-mod attendance;
+#[axum::debug_handler]
+async fn del_card(State(state): State<&'static UsrState>, user: User, Json(DeleteCard { card }): Json<DeleteCard>) -> (StatusCode, &'static str) {
+    let card = match parse_badge(&card) {
+        Ok(Badge::Card(card)) => card,
+        Ok(Badge::Uid(_)) => return (StatusCode::BAD_REQUEST, "Card is a uID"),
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let Some(before) = card::Entity::find_by_id(&card).one(tx).await? else {
+            return Ok(false);
+        };
+        card::Entity::delete_by_id(&card).exec(tx).await?;
+        audit::record(tx, Some(user.id), "del_card", "card", &card, json!(before), Value::Null).await?;
+        Ok::<_, sea_orm::DbErr>(true)
+    })).await;
+
+    match result {
+        Ok(false) => (StatusCode::BAD_REQUEST, "Card is not registered"),
+        Ok(true) => {
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to delete card: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LeaderboardEntry {
+    uid: u32,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+    total_hours: f64,
+    /// Weeks with at least one check-in
+    weeks_attended: u32,
+    /// Most consecutive weeks with check-ins
+    longest_streak: u32,
+    /// Consecutive weeks with check-ins up to the last week of the range,
+    /// which is 0 if the member did not check in that week
+    current_streak: u32,
+    /// Rank by total hours within each team the member is on in the current term, where 1 is the most
+    ranks: BTreeMap<String, u32>,
+}
+
+/// Finds the most consecutive weeks in `weeks`, which are Mondays, and how many consecutive weeks
+/// end at `last_week`, which is 0 if `last_week` is not one of them
+fn streaks(weeks: &BTreeSet<Date>, last_week: Date) -> (u32, u32) {
+    let mut longest_streak = 0;
+    let mut streak = 0;
+    let mut previous: Option<Date> = None;
+    for &week in weeks {
+        streak = match previous {
+            Some(previous) if previous + Days::new(7) == week => streak + 1,
+            _ => 1,
+        };
+        longest_streak = longest_streak.max(streak);
+        previous = Some(week);
+    }
+    (longest_streak, if previous == Some(last_week) { streak } else { 0 })
+}
+
+/// Ranks members by total hours and computes their streaks of weeks with check-ins, filtered by day
+#[axum::debug_handler]
+async fn get_leaderboard(State(state): State<&'static UsrState>, Query(query): Query<AttendanceQuery>) -> Response {
+    let (check_in_condition, session_condition) = match (
+        query.condition(attendance::Column::Uid, attendance::Column::Date),
+        query.condition(session::Column::Uid, session::Column::Start),
+    ) {
+        (Ok(check_in_condition), Ok(session_condition)) => (check_in_condition, session_condition),
+        (Err(msg), _) | (_, Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let (check_ins, sessions, uid_members, member_teams) = tokio::join!(
+        attendance::Entity::find().filter(check_in_condition).all(&state.db),
+        session::Entity::find()
+            .filter(session_condition)
+            .filter(session::Column::End.is_not_null())
+            .filter(session::Column::AutoClosed.eq(false))
+            .all(&state.db),
+        members::by_uid(&state.db),
+        scheduler::member_teams(&state.db, state.time_zone),
+    );
+    let (check_ins, sessions, uid_members, member_teams) = match (check_ins, sessions, uid_members, member_teams) {
+        (Ok(check_ins), Ok(sessions), Ok(uid_members), Ok(member_teams)) => (check_ins, sessions, uid_members, member_teams),
+        (Err(e), _, _, _) | (_, Err(e), _, _) | (_, _, Err(e), _) | (_, _, _, Err(e)) => {
+            error!("Failed to get leaderboard: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut weeks = BTreeMap::<u32, BTreeSet<Date>>::new();
+    for check_in in check_ins {
+        weeks.entry(check_in.uid).or_default().insert(week_of(check_in.date.date()));
+    }
+    let mut hours = BTreeMap::<u32, f64>::new();
+    for session in sessions {
+        if let Some(end) = session.end {
+            *hours.entry(session.uid).or_default() += hours_between(session.start, end);
+        }
+    }
+
+    let last_week = week_of(query.to.unwrap_or_else(|| now_in(state.time_zone).date()));
+    let mut entries: Vec<_> = weeks
+        .into_iter()
+        .map(|(uid, weeks)| {
+            let (longest_streak, current_streak) = streaks(&weeks, last_week);
+            LeaderboardEntry {
+                uid,
+                name: uid_members.get(&uid).map(|member| member.name.clone()),
+                total_hours: hours.get(&uid).copied().unwrap_or_default(),
+                weeks_attended: weeks.len() as u32,
+                longest_streak,
+                current_streak,
+                ranks: BTreeMap::new(),
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| b.total_hours.total_cmp(&a.total_hours).then(a.uid.cmp(&b.uid)));
+
+    // Entries are sorted by hours, so ranks only have to count upwards within each team,
+    // while members with the same hours share a rank
+    let mut teams = BTreeMap::<String, (u32, u32, f64)>::new();
+    for entry in &mut entries {
+        let member_teams = uid_members
+            .get(&entry.uid)
+            .and_then(|member| member_teams.get(&member.id));
+        for team in member_teams.into_iter().flatten() {
+            let (count, rank, last_hours) = teams.entry(team.clone()).or_insert((0, 0, f64::NAN));
+            *count += 1;
+            if entry.total_hours != *last_hours {
+                *rank = *count;
+                *last_hours = entry.total_hours;
+            }
+            entry.ranks.insert(team.clone(), *rank);
+        }
+    }
+
+    Json(entries).into_response()
+}
+
+#[derive(Serialize)]
+struct UnderRequirement {
+    uid: Option<u32>,
+    name: String,
+    hours: f64,
+}
+
+#[derive(Serialize)]
+struct TeamCompliance {
+    #[serde(skip)]
+    code: String,
+    team: String,
+    required_hours: f64,
+    /// Members of the team that spent fewer hours in the lab than required
+    members: Vec<UnderRequirement>,
+}
+
+#[derive(Serialize)]
+struct Compliance {
+    /// The Monday that the week starts on
+    week: Date,
+    /// Teams with a requirement, where teams and their members are from the current term
+    teams: Vec<TeamCompliance>,
+}
+
+impl Compliance {
+    async fn load(db: &impl ConnectionTrait, time_zone: Tz, week: Date) -> Result<Self, sea_orm::DbErr> {
+        let start = week.and_time(NaiveTime::MIN);
+        let (sessions, all_members, member_teams, team_infos) = tokio::join!(
+            session::Entity::find()
+                .filter(session::Column::Start.gte(start))
+                .filter(session::Column::Start.lt(start + Days::new(7)))
+                .filter(session::Column::End.is_not_null())
+                .filter(session::Column::AutoClosed.eq(false))
+                .all(db),
+            members::member::Entity::find().order_by_asc(members::member::Column::Name).all(db),
+            scheduler::member_teams(db, time_zone),
+            team_info::Entity::find()
+                .filter(team_info::Column::Retired.eq(false))
+                .filter(team_info::Column::RequiredHours.gt(0.0))
+                .order_by_asc(team_info::Column::Name)
+                .all(db),
+        );
+        let (all_members, member_teams) = (all_members?, member_teams?);
+
+        let mut hours = BTreeMap::<u32, f64>::new();
+        for session in sessions? {
+            if let Some(end) = session.end {
+                *hours.entry(session.uid).or_default() += hours_between(session.start, end);
+            }
+        }
+
+        let teams = team_infos?
+            .into_iter()
+            .map(|team_info| TeamCompliance {
+                members: all_members
+                    .iter()
+                    .filter(|member| member_teams.get(&member.id).is_some_and(|teams| teams.contains(&team_info.name)))
+                    .map(|member| UnderRequirement {
+                        uid: member.uid,
+                        name: member.name.clone(),
+                        hours: member.uid.and_then(|uid| hours.get(&uid)).copied().unwrap_or_default(),
+                    })
+                    .filter(|member| member.hours < team_info.required_hours)
+                    .collect(),
+                code: team_info.code,
+                team: team_info.name,
+                required_hours: team_info.required_hours,
+            })
+            .collect();
+
+        Ok(Self { week, teams })
+    }
+
+    fn digest_heading(&self) -> String {
+        format!("**Under required hours for the week of {}**", self.week.format("%b %-d"))
+    }
+
+    /// Lines of the weekly digest with the code of the team that each is about.
+    /// Teams without anyone under their requirement are left out.
+    fn digest_lines(&self) -> Vec<(&str, String)> {
+        self.teams
+            .iter()
+            .filter(|team| !team.members.is_empty())
+            .map(|team| (team.code.as_str(), team.webhook_message()))
+            .collect()
+    }
+}
+
+impl TeamCompliance {
+    fn webhook_message(&self) -> String {
+        let members: Vec<_> = self
+            .members
+            .iter()
+            .map(|member| format!("{} ({:.1}h)", member.name, member.hours))
+            .collect();
+        format!("**{}** requires {:.1}h: {}", self.team, self.required_hours, members.join(", "))
+    }
+}
+
+/// Gets the Monday that starts the week of `date`
+fn week_of(date: Date) -> Date {
+    date - Days::new(date.weekday().num_days_from_monday() as u64)
+}
 
 #[derive(Deserialize)]
-struct CheckIn {
-    uid: String,
+struct ComplianceQuery {
+    /// Any day in the week, defaulting to the current week
+    #[serde(default)]
+    week: Option<Date>,
 }
 
+/// Lists the members of each team that are under the team's required hours for a week
 #[axum::debug_handler]
-async fn add_attendance(
-    State(state): State<&'static UsrState>,
-    Form(CheckIn { uid }): Form<CheckIn>,
-) -> (StatusCode, &'static str) {
-    let Some(uid) = uid.strip_prefix('u').or_else(|| uid.strip_prefix('U')) else {
-        return (StatusCode::BAD_REQUEST, "");
+async fn get_compliance(State(state): State<&'static UsrState>, Query(query): Query<ComplianceQuery>) -> Response {
+    let week = week_of(query.week.unwrap_or_else(|| now_in(state.time_zone).date()));
+
+    match Compliance::load(&state.db, state.time_zone, week).await {
+        Ok(compliance) => Json(compliance).into_response(),
+        Err(e) => {
+            error!("Failed to get compliance: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Finds the events whose windows contain `time`
+async fn events_at(db: &impl ConnectionTrait, time: DateTime) -> Result<Vec<event::Model>, sea_orm::DbErr> {
+    event::Entity::find()
+        .filter(event::Column::Start.lte(time))
+        .filter(event::Column::End.gt(time))
+        .order_by_asc(event::Column::Start)
+        .all(db)
+        .await
+}
+
+#[derive(Deserialize)]
+struct PendingEvent {
+    /// Only used when changing an event
+    #[serde(default)]
+    id: u32,
+    title: String,
+    start: DateTime,
+    end: DateTime,
+}
+
+impl PendingEvent {
+    fn check(self) -> Result<event::Model, &'static str> {
+        let title = self.title.trim().to_string();
+        if title.is_empty() {
+            return Err("Title is empty");
+        }
+        if self.end <= self.start {
+            return Err("Event must end after it starts");
+        }
+        Ok(event::Model {
+            id: self.id,
+            title,
+            start: self.start,
+            end: self.end,
+        })
+    }
+}
+
+#[axum::debug_handler]
+async fn new_event(State(state): State<&'static UsrState>, user: User, Json(pending_event): Json<PendingEvent>) -> Response {
+    let model = match pending_event.check() {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let mut active_model: event::ActiveModel = model.into();
+    active_model.id = ActiveValue::NotSet;
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.insert(tx).await?;
+        audit::record(tx, Some(user.id), "new_event", "event", model.id, Value::Null, json!(model)).await?;
+        Ok::<_, sea_orm::DbErr>(model)
+    })).await;
+
+    match result {
+        Ok(model) => {
+            backup_db(state);
+            Json(model).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create event: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Changes the title or window of an event, which changes the check-ins attributed to it
+#[axum::debug_handler]
+async fn change_event(State(state): State<&'static UsrState>, user: User, Json(pending_event): Json<PendingEvent>) -> Response {
+    let model = match pending_event.check() {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let active_model = event::ActiveModel {
+        id: ActiveValue::Unchanged(model.id),
+        title: ActiveValue::Set(model.title),
+        start: ActiveValue::Set(model.start),
+        end: ActiveValue::Set(model.end),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let Some(before) = event::Entity::find_by_id(model.id).one(tx).await? else {
+            return Ok(None);
+        };
+        let model = active_model.update(tx).await?;
+        audit::record(tx, Some(user.id), "change_event", "event", model.id, json!(before), json!(model)).await?;
+        Ok::<_, sea_orm::DbErr>(Some(model))
+    })).await;
+
+    match result {
+        Ok(Some(model)) => {
+            backup_db(state);
+            Json(model).into_response()
+        }
+        Ok(None) => (StatusCode::BAD_REQUEST, "Event not found").into_response(),
+        Err(e) => {
+            error!("Failed to change event: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteEvent {
+    id: u32,
+}
+
+#[axum::debug_handler]
+async fn del_event(State(state): State<&'static UsrState>, user: User, Json(DeleteEvent { id }): Json<DeleteEvent>) -> (StatusCode, &'static str) {
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let Some(before) = event::Entity::find_by_id(id).one(tx).await? else {
+            return Ok(false);
+        };
+        event_mark::Entity::delete_many()
+            .filter(event_mark::Column::Event.eq(id))
+            .exec(tx)
+            .await?;
+        event::Entity::delete_by_id(id).exec(tx).await?;
+        audit::record(tx, Some(user.id), "del_event", "event", id, json!(before), Value::Null).await?;
+        Ok::<_, sea_orm::DbErr>(true)
+    })).await;
+
+    match result {
+        Ok(false) => (StatusCode::BAD_REQUEST, "Event not found"),
+        Ok(true) => {
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to delete event: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Attendee {
+    uid: u32,
+    /// Name of the member with the uID, if there is one
+    name: Option<String>,
+    /// Start of the first session that overlaps the event, which can be before the event
+    /// started, or `None` if the member was only marked present
+    checked_in: Option<DateTime>,
+    /// Whether an organizer marked the member present
+    marked: bool,
+}
+
+/// Gets everyone who was in the lab during each event or was marked present at it, ordered by uID
+/// and keyed by event id. Members were in the lab if one of their sessions overlaps the event.
+async fn event_attendees(db: &impl ConnectionTrait, events: &[event::Model]) -> Result<HashMap<u32, Vec<Attendee>>, sea_orm::DbErr> {
+    let (Some(start), Some(end)) = (
+        events.iter().map(|event| event.start).min(),
+        events.iter().map(|event| event.end).max(),
+    ) else {
+        return Ok(HashMap::new());
+    };
+    let (sessions, marks, uid_members) = tokio::join!(
+        session::Entity::find()
+            .filter(session::Column::Start.lt(end))
+            .filter(Condition::any().add(session::Column::End.is_null()).add(session::Column::End.gt(start)))
+            .order_by_asc(session::Column::Start)
+            .all(db),
+        event_mark::Entity::find()
+            .filter(event_mark::Column::Event.is_in(events.iter().map(|event| event.id)))
+            .all(db),
+        members::by_uid(db),
+    );
+    let (sessions, marks, uid_members) = (sessions?, marks?, uid_members?);
+
+    let attendee = |uid: u32| Attendee {
+        uid,
+        name: uid_members.get(&uid).map(|member| member.name.clone()),
+        checked_in: None,
+        marked: false,
+    };
+    let mut out = HashMap::with_capacity(events.len());
+    for event in events {
+        let mut attendees = BTreeMap::<u32, Attendee>::new();
+        for session in &sessions {
+            let overlaps = if session.auto_closed {
+                // When the member left is unknown, so only a check-in during the event counts
+                session.start >= event.start && session.start < event.end
+            } else {
+                session.start < event.end && session.end.is_none_or(|end| end > event.start)
+            };
+            if overlaps {
+                attendees
+                    .entry(session.uid)
+                    .or_insert_with(|| attendee(session.uid))
+                    .checked_in
+                    .get_or_insert(session.start);
+            }
+        }
+        for mark in marks.iter().filter(|mark| mark.event == event.id) {
+            attendees.entry(mark.uid).or_insert_with(|| attendee(mark.uid)).marked = true;
+        }
+        out.insert(event.id, attendees.into_values().collect());
+    }
+
+    Ok(out)
+}
+
+#[derive(Serialize)]
+struct EventInfo {
+    #[serde(flatten)]
+    event: event::Model,
+    headcount: usize,
+}
+
+#[derive(Deserialize)]
+struct EventQuery {
+    /// First day of events to list
+    #[serde(default)]
+    from: Option<Date>,
+    /// Last day of events to list
+    #[serde(default)]
+    to: Option<Date>,
+}
+
+/// Lists events that start within a range of days along with their headcounts
+#[axum::debug_handler]
+async fn list_events(State(state): State<&'static UsrState>, Query(query): Query<EventQuery>) -> Response {
+    let mut select = event::Entity::find().order_by_asc(event::Column::Start);
+    if let Some(from) = query.from {
+        select = select.filter(event::Column::Start.gte(from.and_time(NaiveTime::MIN)));
+    }
+    if let Some(to) = query.to {
+        let Some(end) = to.checked_add_days(Days::new(1)) else {
+            return (StatusCode::BAD_REQUEST, "Invalid date").into_response();
+        };
+        select = select.filter(event::Column::Start.lt(end.and_time(NaiveTime::MIN)));
+    }
+    let result = async {
+        let events = select.all(&state.db).await?;
+        let attendees = event_attendees(&state.db, &events).await?;
+        Ok::<_, sea_orm::DbErr>(
+            events
+                .into_iter()
+                .map(|event| EventInfo {
+                    headcount: attendees.get(&event.id).map_or(0, Vec::len),
+                    event,
+                })
+                .collect::<Vec<_>>(),
+        )
+    }.await;
+
+    match result {
+        Ok(events) => Json(events).into_response(),
+        Err(e) => {
+            error!("Failed to list events: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EventId {
+    event: u32,
+}
+
+async fn find_event(db: &DatabaseConnection, id: u32) -> Result<event::Model, Response> {
+    match event::Entity::find_by_id(id).one(db).await {
+        Ok(Some(model)) => Ok(model),
+        Ok(None) => Err((StatusCode::BAD_REQUEST, "Event not found").into_response()),
+        Err(e) => {
+            error!("Failed to find event: {e}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "").into_response())
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Roster {
+    event: event::Model,
+    attendees: Vec<Attendee>,
+}
+
+#[axum::debug_handler]
+async fn get_roster(State(state): State<&'static UsrState>, Query(EventId { event }): Query<EventId>) -> Response {
+    let event = match find_event(&state.db, event).await {
+        Ok(x) => x,
+        Err(response) => return response,
     };
-    let Ok(uid) = uid.parse::<u32>() else {
-        return (StatusCode::BAD_REQUEST, "");
+
+    match event_attendees(&state.db, std::slice::from_ref(&event)).await {
+        Ok(mut attendees) => {
+            let attendees = attendees.remove(&event.id).unwrap_or_default();
+            Json(Roster { event, attendees }).into_response()
+        }
+        Err(e) => {
+            error!("Failed to get roster: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Headcount {
+    event: event::Model,
+    /// Members that checked in or were marked present
+    headcount: usize,
+    /// Members that were in the lab during the event
+    checked_in: usize,
+    /// Members that were marked present without checking in
+    marked_only: usize,
+}
+
+#[axum::debug_handler]
+async fn get_headcount(State(state): State<&'static UsrState>, Query(EventId { event }): Query<EventId>) -> Response {
+    let event = match find_event(&state.db, event).await {
+        Ok(x) => x,
+        Err(response) => return response,
     };
-    let active_model = attendance::ActiveModel {
-        uid: ActiveValue::Set(uid),
-        date: ActiveValue::Set(Local::now().naive_local()),
+
+    match event_attendees(&state.db, std::slice::from_ref(&event)).await {
+        Ok(mut attendees) => {
+            let attendees = attendees.remove(&event.id).unwrap_or_default();
+            let checked_in = attendees.iter().filter(|attendee| attendee.checked_in.is_some()).count();
+            Json(Headcount {
+                event,
+                headcount: attendees.len(),
+                checked_in,
+                marked_only: attendees.len() - checked_in,
+            }).into_response()
+        }
+        Err(e) => {
+            error!("Failed to get headcount: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MarkPresent {
+    event: u32,
+    uid: String,
+}
+
+/// Marks a member present at an event for organizers, such as when they forgot to check in
+#[axum::debug_handler]
+async fn mark_present(State(state): State<&'static UsrState>, user: User, Json(MarkPresent { event, uid }): Json<MarkPresent>) -> Response {
+    let uid = match check_uid(&uid) {
+        Ok(uid) => uid,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
     };
+    if let Err(response) = find_event(&state.db, event).await {
+        return response;
+    }
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        // Marking someone present twice keeps the first mark
+        if event_mark::Entity::find_by_id((event, uid)).one(tx).await?.is_some() {
+            return Ok(());
+        }
+        let model = event_mark::ActiveModel {
+            event: ActiveValue::Set(event),
+            uid: ActiveValue::Set(uid),
+            marked: ActiveValue::Set(now_in(state.time_zone)),
+        }.insert(tx).await?;
+        audit::record(tx, Some(user.id), "mark_present", "event_mark", event, Value::Null, json!(model)).await
+    })).await;
 
-    match active_model.insert(&state.db).await {
+    match result {
         Ok(_) => {
+            backup_db(state);
+            (StatusCode::OK, "").into_response()
+        }
+        Err(e) => {
+            error!("Failed to mark present: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Undoes marking a member present, which does not affect their check-ins
+#[axum::debug_handler]
+async fn del_present(State(state): State<&'static UsrState>, user: User, Json(MarkPresent { event, uid }): Json<MarkPresent>) -> (StatusCode, &'static str) {
+    let uid = match check_uid(&uid) {
+        Ok(uid) => uid,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let Some(before) = event_mark::Entity::find_by_id((event, uid)).one(tx).await? else {
+            return Ok(false);
+        };
+        event_mark::Entity::delete_by_id((event, uid)).exec(tx).await?;
+        audit::record(tx, Some(user.id), "del_present", "event_mark", event, json!(before), Value::Null).await?;
+        Ok::<_, sea_orm::DbErr>(true)
+    })).await;
+
+    match result {
+        Ok(false) => (StatusCode::BAD_REQUEST, "Member is not marked present"),
+        Ok(true) => {
             backup_db(state);
             (StatusCode::OK, "")
         }
         Err(e) => {
-            error!("Failed to add attendance: {e}");
+            error!("Failed to unmark present: {e}");
             (StatusCode::INTERNAL_SERVER_ERROR, "")
         }
     }
 }
 
+/// Id of the weekly digest in webhooks, which is out of the range of the
+/// ids of meetings so that it never replaces an announcement
+const WEEKLY_DIGEST_ID: u32 = u32::MAX;
+
+/// Sends the members under their team's required hours to `attendance_webhook`
+/// and to each team's webhook once the week is over
+pub fn spawn_weekly_digest(state: &'static UsrState) {
+    tokio::spawn(async move {
+        loop {
+            let next_week = (week_of(now_in(state.time_zone).date()) + Days::new(7)).and_time(NaiveTime::MIN);
+            sleep_until(state.time_zone, next_week).await;
+
+            let week = week_of(now_in(state.time_zone).date()) - Days::new(7);
+            let compliance = match Compliance::load(&state.db, state.time_zone, week).await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("Failed to get compliance: {e}");
+                    continue;
+                }
+            };
+            let heading = compliance.digest_heading();
+            let lines = compliance.digest_lines();
+            for (code, line) in &lines {
+                if let Some(team_webhook) = scheduler::team_webhook(state, code) {
+                    team_webhook.enqueue(WEEKLY_DIGEST_ID, format!("{heading}\n{line}"));
+                }
+            }
+            if let (Some(webhook), false) = (&state.attendance_webhook, lines.is_empty()) {
+                let lines: Vec<_> = lines.into_iter().map(|(_, line)| line).collect();
+                webhook.enqueue(WEEKLY_DIGEST_ID, format!("{heading}\n{}", lines.join("\n")));
+            }
+        }
+    });
+}
+
 pub fn router() -> Router<&'static UsrState> {
     Router::new()
+        .route("/new/attendance", post(new_attendance))
+        .route("/change/attendance", post(change_attendance))
+        .route("/del/attendance", delete(del_attendance))
+        .route("/new/event", post(new_event))
+        .route("/change/event", post(change_event))
+        .route("/del/event", delete(del_event))
+        .route("/mark/present", post(mark_present))
+        .route("/del/present", delete(del_present))
+        .route("/del/card", delete(del_card))
+        .route_layer(middleware::from_fn_with_state(Scope::AttendanceWrite, accounts::require_user))
+        // These are deliberately outside `require_user`, since kiosks have no user. The handlers
+        // authenticate them: check-ins need a check-in token, a kiosk key or an attendance:kiosk
+        // token, and cards can also be registered by a logged in user.
         .route("/add/attendance", post(add_attendance))
+        .route("/register/card", post(register_card))
+        .route("/get/token", get(get_token))
+        .route("/get/occupancy", get(get_occupancy))
+        .route("/stream/occupancy", get(stream_occupancy))
+        .route("/list/attendance", get(list_attendance))
+        .route("/get/daily", get(get_daily_attendance))
+        .route("/get/members", get(get_member_attendance))
+        .route("/list/correction", get(list_corrections))
+        .route("/export/attendance", get(export_attendance))
+        .route("/list/session", get(list_sessions))
+        .route("/get/hours", get(get_hours))
+        .route("/get/compliance", get(get_compliance))
+        .route("/get/leaderboard", get(get_leaderboard))
+        .route("/list/event", get(list_events))
+        .route("/get/roster", get(get_roster))
+        .route("/get/headcount", get(get_headcount))
+        .route("/list/card", get(list_cards))
+        .route_layer(middleware::from_fn_with_state(Scope::AttendanceRead, accounts::require_scope))
+}
+
+pub async fn init_tables(db: &DatabaseConnection, time_zone: Tz) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+    let had_sessions = migration::has_column(db, "attendance_sessions", "id").await?;
+
+    db.execute(builder.build(schema.create_table_from_entity(attendance::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(session::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(card::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(correction::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(event::Entity).if_not_exists()))
+        .await?;
+    db.execute(builder.build(schema.create_table_from_entity(event_mark::Entity).if_not_exists()))
+        .await?;
+
+    // Check-ins used to be recorded without sessions, so pair them up like they would have been
+    if !had_sessions {
+        let tx = db.begin().await?;
+        replay_sessions(&tx, now_in(time_zone).date(), None).await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
 }
 
 pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
@@ -58,6 +1898,277 @@ pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr>
         .await?;
     db.execute(builder.build(&schema.create_table_from_entity(attendance::Entity)))
         .await?;
+    db.execute(builder.build(Table::drop().table(session::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(session::Entity)))
+        .await?;
+    db.execute(builder.build(Table::drop().table(card::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(card::Entity)))
+        .await?;
+    db.execute(builder.build(Table::drop().table(correction::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(correction::Entity)))
+        .await?;
+    db.execute(builder.build(Table::drop().table(event::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(event::Entity)))
+        .await?;
+    db.execute(builder.build(Table::drop().table(event_mark::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(event_mark::Entity)))
+        .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    async fn db(name: &str) -> DatabaseConnection {
+        let db = crate::test_db(&format!("attendance-{name}")).await;
+        crate::init_tables(&db, chrono_tz::America::Denver).await.unwrap();
+        db
+    }
+
+    /// A day in October 2026, where the 12th is a Monday
+    fn day(day: u32) -> Date {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    fn at(date: u32, hour: u32, minute: u32) -> DateTime {
+        day(date).and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn check_ins_pair_into_sessions() {
+        let db = db("sessions").await;
+        let open = record_check_in(&db, 1234567, at(12, 9, 0)).await.unwrap();
+        assert_eq!(open.end, None);
+        let closed = record_check_in(&db, 1234567, at(12, 11, 30)).await.unwrap();
+        assert_eq!((closed.id, closed.start, closed.end), (open.id, at(12, 9, 0), Some(at(12, 11, 30))));
+        assert_eq!(hours_between(closed.start, at(12, 11, 30)), 2.5);
+
+        // Other members have their own sessions
+        let other = record_check_in(&db, 7654321, at(12, 10, 0)).await.unwrap();
+        let reopened = record_check_in(&db, 1234567, at(12, 13, 0)).await.unwrap();
+        assert_ne!(reopened.id, open.id);
+        assert_eq!((other.end, reopened.end), (None, None));
+    }
+
+    #[tokio::test]
+    async fn stale_sessions_close_at_midnight() {
+        let db = db("stale").await;
+        record_check_in(&db, 1234567, at(12, 20, 0)).await.unwrap();
+        record_check_in(&db, 7654321, at(13, 8, 0)).await.unwrap();
+
+        // Nothing is stale on the day it started
+        assert!(close_stale_sessions(&db, day(12), None).await.unwrap().is_empty());
+        let closed = close_stale_sessions(&db, day(13), None).await.unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].uid, closed[0].end, closed[0].auto_closed), (1234567, Some(at(13, 0, 0)), true));
+        assert!(close_stale_sessions(&db, day(13), None).await.unwrap().is_empty());
+
+        // A check-in the next day closes the stale session of the member before opening a new one
+        let open = record_check_in(&db, 7654321, at(14, 9, 0)).await.unwrap();
+        assert_eq!((open.start, open.end), (at(14, 9, 0), None));
+        let sessions = session::Entity::find()
+            .filter(session::Column::Uid.eq(7654321))
+            .order_by_asc(session::Column::Start)
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!((sessions[0].end, sessions[0].auto_closed), (Some(at(14, 0, 0)), true));
+    }
+
+    #[test]
+    fn badges() {
+        let card = |card: &str| Ok(Badge::Card(card.to_string()));
+        assert_eq!(parse_badge("u1234567"), Ok(Badge::Uid(1234567)));
+        assert_eq!(parse_badge(" U1234567\n"), Ok(Badge::Uid(1234567)));
+        assert_eq!(parse_badge(";0123456789?"), card("0123456789"));
+        assert_eq!(parse_badge(";0123456789=2512?"), card("0123456789"));
+        assert_eq!(parse_badge("%B0123456789^DOE/JANE^?"), card("0123456789"));
+        assert_eq!(parse_badge("04:a2:b3:c4"), card("04A2B3C4"));
+        assert_eq!(parse_badge("04-A2 B3-C4"), card("04A2B3C4"));
+        assert_eq!(parse_badge("3735928559"), card("3735928559"));
+    }
+
+    #[test]
+    fn invalid_badges() {
+        assert_eq!(parse_badge(""), Err("uID is empty"));
+        assert_eq!(parse_badge("u12a"), Err("uID must be u followed by digits"));
+        assert_eq!(parse_badge(";?"), Err("Unrecognized card format"));
+        assert_eq!(parse_badge("%B^NAME^?"), Err("Unrecognized card format"));
+        assert_eq!(parse_badge("hello"), Err("Unrecognized card format"));
+        assert_eq!(parse_badge("::"), Err("Unrecognized card format"));
+    }
+
+    #[tokio::test]
+    async fn weekly_digest() {
+        let db = db("digest").await;
+        db.execute_unprepared(r#"
+            UPDATE team_info SET required_hours = 3 WHERE code = 'C';
+            UPDATE team_info SET required_hours = 1 WHERE code = 'M';
+            INSERT INTO members (name, uid) VALUES ('Ana', 1111111), ('Bo', 2222222), ('Cy', NULL);
+            INSERT INTO teams (term, member, team) SELECT terms.id, members.id, 'C' FROM terms, members;
+            INSERT INTO teams (term, member, team) SELECT terms.id, members.id, 'M' FROM terms, members WHERE members.name = 'Ana';
+        "#).await.unwrap();
+        // Ana meets both requirements, and Bo forgot to check out once, which does not count
+        for (uid, start, end) in [
+            (1111111, at(12, 9, 0), at(12, 12, 0)),
+            (2222222, at(13, 9, 0), at(13, 10, 0)),
+            (2222222, at(14, 9, 0), at(15, 0, 0)),
+            // The next week
+            (2222222, at(19, 9, 0), at(19, 12, 0)),
+        ] {
+            record_check_in(&db, uid, start).await.unwrap();
+            if end.time() == NaiveTime::MIN {
+                close_stale_sessions(&db, end.date(), None).await.unwrap();
+            } else {
+                record_check_in(&db, uid, end).await.unwrap();
+            }
+        }
+
+        let compliance = Compliance::load(&db, chrono_tz::America::Denver, day(12)).await.unwrap();
+        assert_eq!(compliance.digest_heading(), "**Under required hours for the week of Oct 12**");
+        assert_eq!(compliance.digest_lines(), [("C", "**Software** requires 3.0h: Bo (1.0h), Cy (0.0h)".to_string())]);
+
+        let compliance = Compliance::load(&db, chrono_tz::America::Denver, day(19)).await.unwrap();
+        assert_eq!(compliance.digest_lines(), [
+            ("M", "**Mechanical** requires 1.0h: Ana (0.0h)".to_string()),
+            ("C", "**Software** requires 3.0h: Ana (0.0h), Cy (0.0h)".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn event_attendance() {
+        let db = db("events").await;
+        let mut events = vec![];
+        for (title, start, end) in [("Kickoff", at(12, 18, 0), at(12, 20, 0)), ("Workshop", at(13, 18, 0), at(13, 20, 0))] {
+            let event = event::ActiveModel {
+                id: ActiveValue::NotSet,
+                title: ActiveValue::Set(title.into()),
+                start: ActiveValue::Set(start),
+                end: ActiveValue::Set(end),
+            };
+            events.push(event.insert(&db).await.unwrap());
+        }
+        for (uid, time) in [
+            (4, at(12, 12, 0)),
+            (1, at(12, 17, 0)),
+            (1, at(12, 18, 30)),
+            // Forgot to check out, but checked in during the event
+            (3, at(12, 19, 0)),
+            // Arrived as the event ended
+            (2, at(12, 20, 0)),
+            (2, at(12, 21, 0)),
+            (5, at(13, 19, 30)),
+        ] {
+            record_check_in(&db, uid, time).await.unwrap();
+        }
+        // Sessions left open count for events they started in, since when the member left is unknown
+        close_stale_sessions(&db, day(13), None).await.unwrap();
+        for uid in [1, 2] {
+            let mark = event_mark::ActiveModel {
+                event: ActiveValue::Set(events[0].id),
+                uid: ActiveValue::Set(uid),
+                marked: ActiveValue::Set(at(12, 19, 0)),
+            };
+            mark.insert(&db).await.unwrap();
+        }
+
+        let attendees = event_attendees(&db, &events).await.unwrap();
+        let attendees = |event: &event::Model| -> Vec<_> {
+            attendees[&event.id].iter().map(|attendee| (attendee.uid, attendee.checked_in, attendee.marked)).collect()
+        };
+        assert_eq!(attendees(&events[0]), [(1, Some(at(12, 17, 0)), true), (2, None, true), (3, Some(at(12, 19, 0)), false)]);
+        assert_eq!(attendees(&events[1]), [(5, Some(at(13, 19, 30)), false)]);
+        assert!(event_attendees(&db, &[]).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn kiosk_keys() {
+        let keys = vec!["lab-kiosk".to_string(), "front-desk".to_string()];
+        assert!(is_kiosk_key(&keys, "lab-kiosk"));
+        assert!(is_kiosk_key(&keys, "front-desk"));
+        assert!(!is_kiosk_key(&keys, "lab-kiosk "));
+        assert!(!is_kiosk_key(&keys, "lab"));
+        assert!(!is_kiosk_key(&keys, ""));
+        assert!(!is_kiosk_key(&[], "lab-kiosk"));
+    }
+
+    #[test]
+    fn csv_fields() {
+        assert_eq!(csv_field("Naj"), "Naj");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("Smith, Jo"), "\"Smith, Jo\"");
+        assert_eq!(csv_field("The \"Lab\""), "\"The \"\"Lab\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        // Formulas are kept as text
+        assert_eq!(csv_field("=SUM(A1:A2)"), "'=SUM(A1:A2)");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("@cmd"), "'@cmd");
+        assert_eq!(csv_field("=1,2"), "\"'=1,2\"");
+    }
+
+    #[tokio::test]
+    async fn corrections_replay_sessions() {
+        let db = db("replay").await;
+        record_check_in(&db, 7654321, at(12, 9, 0)).await.unwrap();
+        // Corrections change check-ins directly and replay the sessions of the member
+        for time in [at(12, 9, 0), at(12, 12, 0), at(12, 8, 0), at(13, 10, 0)] {
+            let check_in = attendance::ActiveModel {
+                uid: ActiveValue::Set(1234567),
+                date: ActiveValue::Set(time),
+            };
+            check_in.insert(&db).await.unwrap();
+        }
+        replay_sessions(&db, day(14), Some(1234567)).await.unwrap();
+
+        let sessions: Vec<_> = session::Entity::find()
+            .order_by_asc(session::Column::Start)
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|session| (session.uid, session.start, session.end, session.auto_closed))
+            .collect();
+        assert_eq!(sessions, [
+            (1234567, at(12, 8, 0), Some(at(12, 9, 0)), false),
+            (7654321, at(12, 9, 0), None, false),
+            (1234567, at(12, 12, 0), Some(at(13, 0, 0)), true),
+            (1234567, at(13, 10, 0), Some(at(14, 0, 0)), true),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn occupancy() {
+        let db = db("occupancy").await;
+        for (uid, time) in [(1, at(12, 20, 0)), (2, at(13, 8, 0)), (3, at(13, 9, 0)), (4, at(13, 7, 0)), (3, at(13, 10, 0))] {
+            record_check_in(&db, uid, time).await.unwrap();
+        }
+        // The session from yesterday has yet to be closed, but nobody stays overnight
+        let uids = |sessions: Vec<session::Model>| -> Vec<_> { sessions.into_iter().map(|session| session.uid).collect() };
+        assert_eq!(uids(open_sessions(&db, day(13)).await.unwrap()), [4, 2]);
+        assert!(open_sessions(&db, day(14)).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn leaderboard_streaks() {
+        let weeks = |days: &[u32]| -> BTreeSet<Date> {
+            days.iter().map(|&days| week_of(day(12) + Days::new(days as u64))).collect()
+        };
+        let last_week = day(12) + Days::new(6 * 7);
+        assert_eq!(streaks(&weeks(&[]), last_week), (0, 0));
+        // Any day counts for its week, and weeks are consecutive across months
+        assert_eq!(streaks(&weeks(&[0, 6, 7, 21, 27]), last_week), (2, 0));
+        assert_eq!(streaks(&weeks(&[0, 14, 21, 28, 42]), last_week), (3, 1));
+        assert_eq!(streaks(&weeks(&[0, 7, 21, 28, 35, 44]), last_week), (4, 4));
+        assert_eq!(streaks(&weeks(&[44]), last_week), (1, 1));
+    }
+}
@@ -0,0 +1,19 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// A magstripe or NFC card that checks in the member with the uID
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "attendance_cards")]
+pub struct Model {
+    /// Card number or serial, normalized by `parse_badge`
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub card: String,
+    pub uid: u32,
+    pub registered: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// A check-in that an admin added, changed or deleted by hand
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "attendance_corrections")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    /// The check-in before the correction, or `None` if one was added
+    pub old_uid: Option<u32>,
+    pub old_date: Option<DateTime>,
+    /// The check-in after the correction, or `None` if one was deleted
+    pub new_uid: Option<u32>,
+    pub new_date: Option<DateTime>,
+    pub reason: String,
+    pub corrected: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// A general meeting, workshop or competition that check-ins during its window are attributed to
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "attendance_events")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub title: String,
+    pub start: DateTime,
+    /// Check-ins at or after this time are not attributed to the event
+    pub end: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
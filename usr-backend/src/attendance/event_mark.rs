@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// A member that an organizer marked present at an event without checking in
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "attendance_event_marks")]
+pub struct Model {
+    /// Id of a row in `attendance_events`
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub event: u32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub uid: u32,
+    pub marked: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
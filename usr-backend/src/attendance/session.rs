@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// Time between a check-in and the check-out after it
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "attendance_sessions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub uid: u32,
+    pub start: DateTime,
+    /// `None` while the member is still checked in
+    pub end: Option<DateTime>,
+    /// Whether the member forgot to check out, so the session was closed at midnight.
+    /// These sessions do not count towards hours
+    pub auto_closed: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,113 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Seconds that each token is valid for before the next one replaces it
+const PERIOD: i64 = 60;
+/// Bytes of the signature that are kept, which keeps QR codes small
+const SIGNATURE_LEN: usize = 16;
+
+/// Signs check-in tokens that rotate every minute, so that a check-in proves
+/// that the member could see the QR code on the lab display.
+///
+/// A token is the index of its period followed by a truncated HMAC of that index,
+/// eg. "29843117.3f1c...". The secret is random for every run of the server.
+pub struct CheckInTokens {
+    secret: [u8; 32],
+}
+
+impl CheckInTokens {
+    pub fn random() -> Self {
+        Self {
+            secret: rand::random(),
+        }
+    }
+
+    fn mac(&self, period: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(&period.to_be_bytes());
+        mac
+    }
+
+    /// Gets the token for the current period and when it stops being shown
+    pub fn current(&self, now: DateTime<Utc>) -> (String, DateTime<Utc>) {
+        let period = now.timestamp().div_euclid(PERIOD);
+        let signature = self.mac(period).finalize().into_bytes();
+        let token = format!("{period}.{}", hex::encode(&signature[..SIGNATURE_LEN]));
+        let expires = DateTime::from_timestamp((period + 1) * PERIOD, 0).unwrap_or(now);
+        (token, expires)
+    }
+
+    /// Checks that a token was signed by this server for the current period. The previous
+    /// period is also accepted, since a member may scan the code just before it rotates.
+    pub fn check(&self, token: &str, now: DateTime<Utc>) -> Result<(), &'static str> {
+        let (period, signature) = token.trim().split_once('.').ok_or("Invalid check-in token")?;
+        let period: i64 = period.parse().map_err(|_| "Invalid check-in token")?;
+        let signature = hex::decode(signature).map_err(|_| "Invalid check-in token")?;
+        if signature.len() != SIGNATURE_LEN || self.mac(period).verify_truncated_left(&signature).is_err() {
+            return Err("Invalid check-in token");
+        }
+
+        let current = now.timestamp().div_euclid(PERIOD);
+        if period > current {
+            return Err("Invalid check-in token");
+        }
+        if period < current - 1 {
+            return Err("Check-in token expired");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn current_token_is_accepted() {
+        let tokens = CheckInTokens::random();
+        let now = at(1_790_000_010);
+        let (token, expires) = tokens.current(now);
+        assert_eq!(expires, at(1_790_000_040));
+        assert_eq!(tokens.check(&token, now), Ok(()));
+        assert_eq!(tokens.check(&format!(" {token}\n"), now), Ok(()));
+    }
+
+    #[test]
+    fn previous_period_is_accepted() {
+        let tokens = CheckInTokens::random();
+        let (token, expires) = tokens.current(at(1_790_000_010));
+        assert_eq!(tokens.check(&token, expires), Ok(()));
+        assert_eq!(tokens.check(&token, at(expires.timestamp() + PERIOD)), Err("Check-in token expired"));
+    }
+
+    #[test]
+    fn future_periods_are_rejected() {
+        let tokens = CheckInTokens::random();
+        let (token, _) = tokens.current(at(1_790_000_100));
+        assert_eq!(tokens.check(&token, at(1_790_000_010)), Err("Invalid check-in token"));
+    }
+
+    #[test]
+    fn forged_tokens_are_rejected() {
+        let tokens = CheckInTokens::random();
+        let now = at(1_790_000_010);
+        let (token, _) = tokens.current(now);
+        let other = CheckInTokens::random().current(now).0;
+        assert_eq!(tokens.check(&other, now), Err("Invalid check-in token"));
+
+        let (period, signature) = token.split_once('.').unwrap();
+        let next = format!("{}.{signature}", period.parse::<i64>().unwrap() - 1);
+        assert_eq!(tokens.check(&next, now), Err("Invalid check-in token"));
+        assert_eq!(tokens.check(&token[..token.len() - 2], now), Err("Invalid check-in token"));
+        assert_eq!(tokens.check(period, now), Err("Invalid check-in token"));
+        assert_eq!(tokens.check("x.00", now), Err("Invalid check-in token"));
+        assert_eq!(tokens.check("", now), Err("Invalid check-in token"));
+    }
+}
@@ -0,0 +1,172 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{Local, NaiveDateTime};
+use sea_orm::{
+    sea_query::Table, ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait,
+    DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Schema,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::error;
+
+use crate::{accounts::{self, Scope}, UsrState};
+
+mod entry;
+
+/// Writes an entry to the audit log. This should be given the transaction that makes the
+/// change, so that the change is never saved without its entry. `before` and `after` are
+/// `Value::Null` when the entity is created or deleted.
+pub async fn record(
+    db: &impl ConnectionTrait,
+    actor: Option<u32>,
+    action: &str,
+    entity: &str,
+    entity_id: impl ToString,
+    before: Value,
+    after: Value,
+) -> Result<(), sea_orm::DbErr> {
+    entry::ActiveModel {
+        id: ActiveValue::NotSet,
+        time: ActiveValue::Set(Local::now().naive_local()),
+        actor: ActiveValue::Set(actor),
+        action: ActiveValue::Set(action.to_string()),
+        entity: ActiveValue::Set(entity.to_string()),
+        entity_id: ActiveValue::Set(entity_id.to_string()),
+        before: ActiveValue::Set((!before.is_null()).then_some(before)),
+        after: ActiveValue::Set((!after.is_null()).then_some(after)),
+    }.insert(db).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    entity: Option<String>,
+    entity_id: Option<String>,
+    /// Username of the user that made the changes
+    actor: Option<String>,
+    /// Earliest time to include
+    from: Option<NaiveDateTime>,
+    /// Latest time to include
+    to: Option<NaiveDateTime>,
+    /// Page to return, counting from 0
+    #[serde(default)]
+    page: u64,
+    page_size: Option<u64>,
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 500;
+
+#[derive(Serialize)]
+struct EntryInfo {
+    #[serde(flatten)]
+    entry: entry::Model,
+    /// Username of the actor
+    actor_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuditPage {
+    page: u64,
+    page_size: u64,
+    total: u64,
+    entries: Vec<EntryInfo>,
+}
+
+/// Lists changes from newest to oldest, which only admins may see
+#[axum::debug_handler]
+async fn list_entries(State(state): State<&'static UsrState>, Query(query): Query<AuditQuery>) -> Response {
+    let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page_size == 0 || page_size > MAX_PAGE_SIZE {
+        return (StatusCode::BAD_REQUEST, "Invalid page size").into_response();
+    }
+    let usernames = match accounts::usernames(&state.db).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to get usernames: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut condition = Condition::all();
+    if let Some(entity) = &query.entity {
+        condition = condition.add(entry::Column::Entity.eq(entity.trim()));
+    }
+    if let Some(entity_id) = &query.entity_id {
+        condition = condition.add(entry::Column::EntityId.eq(entity_id.trim()));
+    }
+    if let Some(actor) = &query.actor {
+        let Some(actor) = usernames.iter().find_map(|(id, username)| (username == actor.trim()).then_some(*id)) else {
+            return (StatusCode::BAD_REQUEST, "User not found").into_response();
+        };
+        condition = condition.add(entry::Column::Actor.eq(actor));
+    }
+    if let Some(from) = query.from {
+        condition = condition.add(entry::Column::Time.gte(from));
+    }
+    if let Some(to) = query.to {
+        condition = condition.add(entry::Column::Time.lte(to));
+    }
+
+    let paginator = entry::Entity::find()
+        .filter(condition)
+        .order_by_desc(entry::Column::Id)
+        .paginate(&state.db, page_size);
+    let (total, entries) = tokio::join!(
+        paginator.num_items(),
+        paginator.fetch_page(query.page),
+    );
+
+    match (total, entries) {
+        (Ok(total), Ok(entries)) => Json(AuditPage {
+            page: query.page,
+            page_size,
+            total,
+            entries: entries
+                .into_iter()
+                .map(|entry| EntryInfo {
+                    actor_name: entry.actor.and_then(|actor| usernames.get(&actor).cloned()),
+                    entry,
+                })
+                .collect(),
+        }).into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to list audit log: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+pub fn router() -> Router<&'static UsrState> {
+    Router::new()
+        .route("/list/entry", get(list_entries))
+        .route_layer(middleware::from_fn_with_state(Scope::AuditRead, accounts::require_user))
+}
+
+pub async fn init_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(schema.create_table_from_entity(entry::Entity).if_not_exists()))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(Table::drop().table(entry::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(entry::Entity)))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// A change that someone made, kept so that we know who changed what and when
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "audit_log")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    pub time: DateTime,
+    /// Id of a row in `users`, which is empty for kiosks and anyone else without an account
+    pub actor: Option<u32>,
+    /// Name of the handler that made the change, such as `update_order`
+    pub action: String,
+    /// Kind of thing that was changed, such as `order`
+    pub entity: String,
+    pub entity_id: String,
+    /// The entity before the change, which is empty if it was created
+    pub before: Option<Json>,
+    /// The entity after the change, which is empty if it was deleted
+    pub after: Option<Json>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,15 +1,17 @@
 use std::{
-    backtrace::Backtrace, io::{LineWriter, Write}, net::SocketAddr, panic::set_hook, path::Path, sync::atomic::AtomicBool
+    backtrace::Backtrace, collections::HashMap, io::{LineWriter, Write}, net::SocketAddr, panic::set_hook, path::Path, sync::{atomic::AtomicBool, Arc}
 };
 
-use axum::{routing::get, Router};
+use axum::{middleware, routing::get, Router};
+use chrono_tz::Tz;
 use discord_webhook2::webhook::DiscordWebhook;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use rustls::crypto::ring::default_provider;
 use sea_orm::{Database, DatabaseConnection};
 use serde::Deserialize;
+use tokio::sync::broadcast;
 use tower::ServiceBuilder;
-use tower_http::cors::Any;
+use tower_http::cors::{AllowHeaders, AllowMethods};
 use tracing::{error, info};
 use tracing_subscriber::FmtSubscriber;
 use webhook::BatchedWebhook;
@@ -19,6 +21,11 @@ mod manifest;
 mod webhook;
 mod backup;
 mod attendance;
+mod members;
+mod accounts;
+mod policy;
+mod audit;
+mod migration;
 
 struct LogWriter {
     inner: &'static Mutex<LineWriter<std::fs::File>>,
@@ -42,15 +49,108 @@ impl Write for LogWriter {
 struct Config {
     new_orders_webhook: Option<String>,
     order_updates_webhook: Option<String>,
+    #[serde(default)]
+    meetings_webhook: Option<String>,
+    /// Receives digests of changes to availabilities and teams
+    #[serde(default)]
+    scheduler_webhook: Option<String>,
+    /// Receives weekly digests of members under their team's required hours
+    #[serde(default)]
+    attendance_webhook: Option<String>,
+    /// Keys that the lab kiosk and display send in the `X-Kiosk-Key` header,
+    /// which lets them check members in without a token and get tokens to show
+    #[serde(default)]
+    kiosk_keys: Vec<String>,
+    /// Created as an admin on startup if there are no users
+    #[serde(default)]
+    first_admin: Option<accounts::FirstAdmin>,
+    /// Lets members log in through the university SSO
+    #[serde(default)]
+    oidc: Option<accounts::OidcConfig>,
+    /// IANA name of the time zone that the organization meets in
+    #[serde(default)]
+    time_zone: Option<String>,
 }
 
+/// Where the frontend is served from in production, which is the only other site that may use the API
+const FRONTEND_ORIGIN: &str = "https://utahrobotics.github.io";
+
 struct UsrState {
     db: DatabaseConnection,
-    new_orders_webhook: Option<BatchedWebhook>,
-    order_updates_webhook: Option<BatchedWebhook>,
+    new_orders_webhook: Option<Arc<BatchedWebhook>>,
+    order_updates_webhook: Option<Arc<BatchedWebhook>>,
+    meetings_webhook: Option<Arc<BatchedWebhook>>,
+    scheduler_webhook: Option<Arc<BatchedWebhook>>,
+    attendance_webhook: Option<Arc<BatchedWebhook>>,
+    /// Changes waiting in `scheduler_webhook`
+    scheduler_digests: Mutex<scheduler::Digests>,
+    /// Webhooks of individual teams, by team code
+    team_webhooks: RwLock<HashMap<String, Arc<BatchedWebhook>>>,
+    time_zone: Tz,
+    check_in_tokens: attendance::CheckInTokens,
+    kiosk_keys: Vec<String>,
+    oidc: Option<accounts::Oidc>,
+    /// Check-ins and check-outs as they happen, for the occupancy stream
+    occupancy_events: broadcast::Sender<attendance::OccupancyEvent>,
     backup_task_running: AtomicBool
 }
 
+/// Creates any missing tables and brings databases from older versions up to date
+async fn init_tables(db: &DatabaseConnection, time_zone: Tz) -> Result<(), sea_orm::DbErr> {
+    members::init_tables(db).await?;
+    accounts::init_tables(db).await?;
+    audit::init_tables(db).await?;
+    attendance::init_tables(db, time_zone).await?;
+    scheduler::init_tables(db, time_zone).await?;
+    Ok(())
+}
+
+/// Opens an empty database in a file for a test, since every connection to an in-memory database has its own
+#[cfg(test)]
+async fn test_db(name: &str) -> DatabaseConnection {
+    let path = std::env::temp_dir().join(format!("usr-{name}-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    Database::connect(format!("sqlite://{}?mode=rwc", path.display())).await.unwrap()
+}
+
+/// Routes of every module, behind the layers that find the user and check what they may do
+fn api_router(state: &'static UsrState) -> Router<&'static UsrState> {
+    Router::new()
+        .nest("/scheduler", scheduler::router())
+        .nest("/manifest", manifest::router())
+        .nest("/attendance", attendance::router())
+        .nest("/members", members::router())
+        .nest("/accounts", accounts::router())
+        .nest("/audit", audit::router())
+        // Layers run from the last one added, so the policy sees the loaded user
+        .layer(middleware::from_fn_with_state(state, policy::enforce))
+        .layer(middleware::from_fn_with_state(state, accounts::load_user))
+}
+
+#[cfg(test)]
+impl UsrState {
+    /// State without webhooks or backups, for tests that send requests through the router
+    fn for_tests(db: DatabaseConnection, kiosk_keys: Vec<String>) -> &'static Self {
+        Box::leak(Box::new(UsrState {
+            db,
+            new_orders_webhook: None,
+            order_updates_webhook: None,
+            meetings_webhook: None,
+            scheduler_webhook: None,
+            attendance_webhook: None,
+            scheduler_digests: Mutex::default(),
+            team_webhooks: RwLock::default(),
+            time_zone: chrono_tz::America::Denver,
+            check_in_tokens: attendance::CheckInTokens::random(),
+            kiosk_keys,
+            oidc: None,
+            occupancy_events: broadcast::Sender::new(64),
+            // Keeps handlers from copying the database into the backup repository
+            backup_task_running: AtomicBool::new(true),
+        }))
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let log_file = Mutex::new(LineWriter::new(std::fs::File::create("usr-backend.log")?));
@@ -86,6 +186,12 @@ async fn main() -> anyhow::Result<()> {
 
     let db = Database::connect("sqlite://usr-db.sqlite?mode=rwc").await?;
     let config: Config = serde_json::from_reader(std::fs::File::open("config.json")?)?;
+    let time_zone = match &config.time_zone {
+        Some(time_zone) => time_zone
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid time zone {time_zone}: {e}"))?,
+        None => chrono_tz::America::Denver,
+    };
 
     if Path::new(".reset-db").exists() {
         info!("Resetting DB");
@@ -104,10 +210,25 @@ async fn main() -> anyhow::Result<()> {
                 attendance::reset_tables(&db).await?;
                 info!("Reset attendance tables");
             }
+            "members" => {
+                members::reset_tables(&db).await?;
+                info!("Reset members tables");
+            }
+            "accounts" => {
+                accounts::reset_tables(&db).await?;
+                info!("Reset accounts tables");
+            }
+            "audit" => {
+                audit::reset_tables(&db).await?;
+                info!("Reset audit tables");
+            }
             "all" => {
                 scheduler::reset_tables(&db).await?;
                 manifest::reset_tables(&db).await?;
                 attendance::reset_tables(&db).await?;
+                members::reset_tables(&db).await?;
+                accounts::reset_tables(&db).await?;
+                audit::reset_tables(&db).await?;
                 info!("Reset all tables");
             }
             _ => {
@@ -119,56 +240,91 @@ async fn main() -> anyhow::Result<()> {
         std::fs::remove_file(".reset-db")?;
     }
 
+    init_tables(&db, time_zone).await?;
+    if let Some(first_admin) = config.first_admin {
+        if accounts::create_first_admin(&db, first_admin).await? {
+            info!("Created the first admin");
+        }
+    }
+    let team_webhooks = scheduler::load_team_webhooks(&db).await?;
+
+    let state: &'static UsrState = Box::leak(Box::new(UsrState {
+        db,
+        new_orders_webhook: {
+            if let Some(new_orders_webhook) = config.new_orders_webhook {
+                Some(Arc::new(DiscordWebhook::new(new_orders_webhook)?.into()))
+            } else {
+                None
+            }
+        },
+        order_updates_webhook: {
+            if let Some(order_updates_webhook) = config.order_updates_webhook {
+                Some(Arc::new(DiscordWebhook::new(order_updates_webhook)?.into()))
+            } else {
+                None
+            }
+        },
+        meetings_webhook: {
+            if let Some(meetings_webhook) = config.meetings_webhook {
+                Some(Arc::new(DiscordWebhook::new(meetings_webhook)?.into()))
+            } else {
+                None
+            }
+        },
+        scheduler_webhook: {
+            if let Some(scheduler_webhook) = config.scheduler_webhook {
+                Some(Arc::new(DiscordWebhook::new(scheduler_webhook)?.into()))
+            } else {
+                None
+            }
+        },
+        attendance_webhook: {
+            if let Some(attendance_webhook) = config.attendance_webhook {
+                Some(Arc::new(DiscordWebhook::new(attendance_webhook)?.into()))
+            } else {
+                None
+            }
+        },
+        scheduler_digests: Mutex::default(),
+        team_webhooks: RwLock::new(team_webhooks),
+        time_zone,
+        check_in_tokens: attendance::CheckInTokens::random(),
+        kiosk_keys: config.kiosk_keys,
+        oidc: config.oidc.map(accounts::Oidc::new),
+        occupancy_events: broadcast::Sender::new(64),
+        backup_task_running: AtomicBool::new(false),
+    }));
+    scheduler::watch_digests(state);
+    attendance::spawn_session_closer(state);
+    attendance::spawn_weekly_digest(state);
+
     let app = Router::new()
         .route(
             "/",
             get(|| async { format!("Version: {}", env!("CARGO_PKG_VERSION")) }),
         )
-        .nest(
-            "/api",
-            Router::new()
-                .nest("/scheduler", scheduler::router())
-                .nest("/manifest", manifest::router())
-                .nest("/attendance", attendance::router()),
-        )
+        .nest("/api", api_router(state))
         .layer(
             ServiceBuilder::new()
                 .layer({
                     let mut layer = tower_http::cors::CorsLayer::new();
                     #[cfg(debug_assertions)]
                     {
-                        layer = layer.allow_origin(Any);
+                        layer = layer.allow_origin(tower_http::cors::AllowOrigin::mirror_request());
                     }
                     #[cfg(not(debug_assertions))]
                     {
-                        layer = layer.allow_origin(
-                            "https://utahrobotics.github.io".parse::<axum::http::HeaderValue>().unwrap(),
-                        );
+                        layer = layer.allow_origin(FRONTEND_ORIGIN.parse::<axum::http::HeaderValue>().unwrap());
                     }
+                    // Credentials are needed for session cookies, which cannot be combined with `Any`
                     layer
-                        .allow_headers(Any)
-                        .allow_methods(Any)
+                        .allow_headers(AllowHeaders::mirror_request())
+                        .allow_methods(AllowMethods::mirror_request())
+                        .allow_credentials(true)
                 })
                 .layer(tower_http::compression::CompressionLayer::new())
         )
-        .with_state(Box::leak(Box::new(UsrState {
-            db,
-            new_orders_webhook: {
-                if let Some(new_orders_webhook) = config.new_orders_webhook {
-                    Some(DiscordWebhook::new(new_orders_webhook)?.into())
-                } else {
-                    None
-                }
-            },
-            order_updates_webhook: {
-                if let Some(order_updates_webhook) = config.order_updates_webhook {
-                    Some(DiscordWebhook::new(order_updates_webhook)?.into())
-                } else {
-                    None
-                }
-            },
-            backup_task_running: AtomicBool::new(false),
-        })));
+        .with_state(state);
 
     default_provider()
         .install_default()
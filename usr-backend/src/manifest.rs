@@ -1,6 +1,7 @@
 use axum::{
     extract::State,
     http::StatusCode,
+    middleware,
     response::{IntoResponse, Response},
     routing::{delete, get, post},
     Json, Router,
@@ -11,9 +12,10 @@ use sea_orm::{
     TransactionTrait,
 };
 use serde::Deserialize;
+use serde_json::{json, Value};
 use tracing::error;
 
-use crate::{backup::backup_db, scheduler, UsrState};
+use crate::{accounts::{self, Scope, User}, audit, backup::backup_db, scheduler, UsrState};
 
 mod order;
 mod order_status;
@@ -24,7 +26,7 @@ pub struct PendingOrder {
     pub count: u32,
     pub unit_cost: Decimal,
     pub store_in: String,
-    pub team: scheduler::Team,
+    pub team: String,
     pub reason: String,
     pub vendor: String,
     pub link: String,
@@ -33,8 +35,17 @@ pub struct PendingOrder {
 #[axum::debug_handler]
 async fn new_order(
     State(state): State<&'static UsrState>,
+    user: User,
     Json(pending_order): Json<PendingOrder>,
 ) -> (StatusCode, &'static str) {
+    let team = match scheduler::find_team(&state.db, &pending_order.team).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Unknown team"),
+        Err(e) => {
+            error!("Failed to find team: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
     let webhook_msg = format!(
         "**New Order!**\n**Name:** {}\n**Vendor:** {}\n**Link:** {}\n**Count:** {}\n**Unit Cost:** ${}\n**Subtotal:** ${}\n**Team:** {}\n**Reason:** {}",
         pending_order.name,
@@ -43,7 +54,7 @@ async fn new_order(
         pending_order.count,
         pending_order.unit_cost,
         Decimal::from(pending_order.count) * pending_order.unit_cost,
-        pending_order.team,
+        team.name,
         pending_order.reason
     );
     let active_model = order::ActiveModel {
@@ -52,7 +63,7 @@ async fn new_order(
         count: ActiveValue::Set(pending_order.count),
         unit_cost: ActiveValue::Set(pending_order.unit_cost),
         store_in: ActiveValue::Set(pending_order.store_in),
-        team: ActiveValue::Set(pending_order.team),
+        team: ActiveValue::Set(team.code.clone()),
         reason: ActiveValue::Set(pending_order.reason),
         vendor: ActiveValue::Set(pending_order.vendor),
         link: ActiveValue::Set(pending_order.link),
@@ -72,6 +83,7 @@ async fn new_order(
                 };
 
                 active_model.insert(tx).await?;
+                audit::record(tx, Some(user.id), "new_order", "order", model.id, Value::Null, json!(model)).await?;
 
                 Result::<_, sea_orm::DbErr>::Ok(model)
             })
@@ -81,10 +93,12 @@ async fn new_order(
     match result {
         Ok(m) => {
             backup_db(state);
-            state
-                .new_orders_webhook
-                .as_ref()
-                .map(|x| x.enqueue(m.id, webhook_msg));
+            if let Some(team_webhook) = scheduler::team_webhook(state, &team.code) {
+                team_webhook.enqueue(m.id, webhook_msg.clone());
+            }
+            if let Some(new_orders_webhook) = &state.new_orders_webhook {
+                new_orders_webhook.enqueue(m.id, webhook_msg);
+            }
             (StatusCode::OK, "")
         }
         Err(e) => {
@@ -101,7 +115,7 @@ pub struct ChangeOrder {
     pub count: u32,
     pub unit_cost: Decimal,
     pub store_in: String,
-    pub team: scheduler::Team,
+    pub team: String,
     pub reason: String,
     pub vendor: String,
     pub link: String,
@@ -110,6 +124,7 @@ pub struct ChangeOrder {
 #[axum::debug_handler]
 async fn change_order(
     State(state): State<&'static UsrState>,
+    user: User,
     Json(change_order): Json<ChangeOrder>,
 ) -> (StatusCode, &'static str) {
     match order_status::Entity::find()
@@ -131,6 +146,22 @@ async fn change_order(
             return (StatusCode::INTERNAL_SERVER_ERROR, "");
         }
     }
+    let before = match order::Entity::find_by_id(change_order.id).one(&state.db).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Order not found"),
+        Err(e) => {
+            error!("Failed to find order: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let team = match scheduler::find_team(&state.db, &change_order.team).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Unknown team"),
+        Err(e) => {
+            error!("Failed to find team: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
     let webhook_msg = format!(
         "***Order Changed***\n**Name:** {}\n**Vendor:** {}\n**Link:** {}\n**Count:** {}\n**Unit Cost:** ${}\n**Subtotal:** ${}\n**Team:** {}\n**Reason:** {}",
         change_order.name,
@@ -139,7 +170,7 @@ async fn change_order(
         change_order.count,
         change_order.unit_cost,
         Decimal::from(change_order.count) * change_order.unit_cost,
-        change_order.team,
+        team.name,
         change_order.reason
     );
     let active_model = order::ActiveModel {
@@ -148,38 +179,53 @@ async fn change_order(
         count: ActiveValue::Set(change_order.count),
         unit_cost: ActiveValue::Set(change_order.unit_cost),
         store_in: ActiveValue::Set(change_order.store_in),
-        team: ActiveValue::Set(change_order.team),
+        team: ActiveValue::Set(team.code.clone()),
         reason: ActiveValue::Set(change_order.reason),
         vendor: ActiveValue::Set(change_order.vendor),
         link: ActiveValue::Set(change_order.link),
         ref_number: ActiveValue::NotSet,
     };
-    if let Err(e) = active_model.update(&state.db).await {
+    let result = state
+        .db
+        .transaction(|tx| {
+            Box::pin(async move {
+                let model = active_model.update(tx).await?;
+                audit::record(tx, Some(user.id), "change_order", "order", model.id, json!(before), json!(model)).await?;
+                Result::<_, sea_orm::DbErr>::Ok(())
+            })
+        })
+        .await;
+    if let Err(e) = result {
         error!("Failed to change order: {e}");
         (StatusCode::INTERNAL_SERVER_ERROR, "")
     } else {
         backup_db(state);
-        state
-            .new_orders_webhook
-            .as_ref()
-            .map(|x| x.enqueue(change_order.id, webhook_msg));
+        if let Some(team_webhook) = scheduler::team_webhook(state, &team.code) {
+            team_webhook.enqueue(change_order.id, webhook_msg.clone());
+        }
+        if let Some(new_orders_webhook) = &state.new_orders_webhook {
+            new_orders_webhook.enqueue(change_order.id, webhook_msg);
+        }
         (StatusCode::OK, "")
     }
 }
 
 #[derive(Deserialize)]
-struct DeleteOrder {
-    id: u32,
+pub struct DeleteOrder {
+    pub id: u32,
     #[serde(default)]
-    force: bool,
+    pub force: bool,
 }
 
 #[axum::debug_handler]
 async fn cancel_order(
     State(state): State<&'static UsrState>,
+    user: User,
     Json(DeleteOrder { id, force }): Json<DeleteOrder>,
 ) -> (StatusCode, &'static str) {
     let webhook_msg;
+    let team_code;
+    let before;
 
     match order_status::Entity::find()
         .filter(order_status::Column::OrderId.eq(id))
@@ -193,16 +239,25 @@ async fn cancel_order(
             }
             let model = match order::Entity::find_by_id(id).one(&state.db).await {
                 Ok(Some(model)) => model,
-                Ok(None) => unreachable!(),
+                Ok(None) => return (StatusCode::BAD_REQUEST, "Order not found"),
                 Err(e) => {
                     error!("Failed to find order: {e}");
                     return (StatusCode::INTERNAL_SERVER_ERROR, "");
                 }
             };
+            let team_name = match scheduler::team_name(&state.db, &model.team).await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("Failed to find team: {e}");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "");
+                }
+            };
             webhook_msg = format!(
                 "***Order Cancelled***\n**Name:** {}\n**Count:** {}\n**Team:** {}",
-                model.name, model.count, model.team,
+                model.name, model.count, team_name,
             );
+            before = json!(model);
+            team_code = model.team;
         }
         Ok(None) => {
             return (StatusCode::BAD_REQUEST, "Order not found");
@@ -213,34 +268,38 @@ async fn cancel_order(
         }
     }
 
-    if force {
-        let result = state
-            .db
-            .transaction(|tx| {
-                Box::pin(async move {
-                    order::Entity::delete_by_id(id).exec(tx).await?;
+    let result = state
+        .db
+        .transaction(|tx| {
+            Box::pin(async move {
+                order::Entity::delete_by_id(id).exec(tx).await?;
+                if force {
                     order_status::Entity::delete_many()
                         .filter(order_status::Column::OrderId.eq(id))
                         .exec(tx)
                         .await?;
-                    Result::<_, sea_orm::DbErr>::Ok(())
-                })
+                }
+                audit::record(tx, Some(user.id), "cancel_order", "order", id, before, Value::Null).await?;
+                Result::<_, sea_orm::DbErr>::Ok(())
             })
-            .await;
+        })
+        .await;
 
-        if let Err(e) = result {
+    if let Err(e) = result {
+        if force {
             error!("Failed to force delete order: {e}");
-            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        } else {
+            error!("Failed to delete order: {e}");
         }
-    } else if let Err(e) = order::Entity::delete_by_id(id).exec(&state.db).await {
-        error!("Failed to delete order: {e}");
         return (StatusCode::INTERNAL_SERVER_ERROR, "");
     }
 
-    state
-        .new_orders_webhook
-        .as_ref()
-        .map(|x| x.enqueue(id, webhook_msg));
+    if let Some(team_webhook) = scheduler::team_webhook(state, &team_code) {
+        team_webhook.enqueue(id, webhook_msg.clone());
+    }
+    if let Some(new_orders_webhook) = &state.new_orders_webhook {
+        new_orders_webhook.enqueue(id, webhook_msg);
+    }
     backup_db(state);
 
     (StatusCode::OK, "")
@@ -253,12 +312,26 @@ pub struct UpdateOrder {
     pub ref_number: Option<u32>,
 }
 
+impl UpdateOrder {
+    pub fn approves(&self) -> bool {
+        self.status == order_status::Status::Approved
+    }
+}
+
+/// Finds the code of the team that an order is for
+pub async fn order_team(db: &impl ConnectionTrait, id: u32) -> Result<Option<String>, sea_orm::DbErr> {
+    Ok(order::Entity::find_by_id(id).one(db).await?.map(|model| model.team))
+}
+
 #[axum::debug_handler]
 async fn update_order(
     State(state): State<&'static UsrState>,
+    user: User,
     Json(update_order): Json<UpdateOrder>,
 ) -> (StatusCode, &'static str) {
     let webhook_msg;
+    let team_code;
+    let before;
     let mut same_status = false;
 
     match order_status::Entity::find()
@@ -267,11 +340,11 @@ async fn update_order(
         .one(&state.db)
         .await
     {
-        Ok(Some(model)) => {
-            if model.status == order_status::Status::InStorage {
+        Ok(Some(status)) => {
+            if status.status == order_status::Status::InStorage {
                 return (StatusCode::BAD_REQUEST, "Order is already in storage");
             }
-            if model.status == update_order.status {
+            if status.status == update_order.status {
                 if update_order.ref_number.is_none() {
                     return (StatusCode::BAD_REQUEST, "Order is already in that state");
                 }
@@ -282,30 +355,39 @@ async fn update_order(
                 .await
             {
                 Ok(Some(model)) => model,
-                Ok(None) => unreachable!(),
+                Ok(None) => return (StatusCode::BAD_REQUEST, "Order not found"),
                 Err(e) => {
                     error!("Failed to find order: {e}");
                     return (StatusCode::INTERNAL_SERVER_ERROR, "");
                 }
             };
+            let team_name = match scheduler::team_name(&state.db, &model.team).await {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("Failed to find team: {e}");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "");
+                }
+            };
             if update_order.status == order_status::Status::InStorage {
                 if model.store_in.is_empty() {
                     webhook_msg = format!(
                         "**Order Complete!**\n**Name:** {}\n**Team:** {}",
-                        model.name, model.team
+                        model.name, team_name
                     );
                 } else {
                     webhook_msg = format!(
                         "**Order Complete!**\n**Name:** {}\n**Team:** {}\n**Location:** {}",
-                        model.name, model.team, model.store_in
+                        model.name, team_name, model.store_in
                     );
                 }
             } else {
                 webhook_msg = format!(
                     "**Order Update!**\n**Name:** {}\n**Team:** {}\n**Status:** {}",
-                    model.name, model.team, update_order.status
+                    model.name, team_name, update_order.status
                 );
             }
+            before = json!({ "status": status.status, "ref_number": model.ref_number });
+            team_code = model.team;
         }
         Ok(None) => {
             return (StatusCode::BAD_REQUEST, "Order not found");
@@ -345,6 +427,8 @@ async fn update_order(
                 };
 
                 active_model.update(tx).await?;
+                let after = json!({ "status": update_order.status, "ref_number": update_order.ref_number });
+                audit::record(tx, Some(user.id), "update_order", "order", update_order.id, before, after).await?;
 
                 Result::<_, sea_orm::DbErr>::Ok(())
             })
@@ -356,10 +440,12 @@ async fn update_order(
         (StatusCode::INTERNAL_SERVER_ERROR, "")
     } else {
         if !same_status {
-            state
-                .order_updates_webhook
-                .as_ref()
-                .map(|x| x.enqueue(update_order.id, webhook_msg));
+            if let Some(team_webhook) = scheduler::team_webhook(state, &team_code) {
+                team_webhook.enqueue(update_order.id, webhook_msg.clone());
+            }
+            if let Some(order_updates_webhook) = &state.order_updates_webhook {
+                order_updates_webhook.enqueue(update_order.id, webhook_msg);
+            }
         }
         backup_db(state);
         (StatusCode::OK, "")
@@ -371,7 +457,21 @@ async fn get_orders(State(state): State<&'static UsrState>) -> Response {
     let result = order::Entity::find().all(&state.db).await;
 
     match result {
-        Ok(orders) => {
+        Ok(mut orders) => {
+            // Orders are stored with team codes, but clients know teams by name
+            match scheduler::team_names(&state.db).await {
+                Ok(team_names) => {
+                    for order in &mut orders {
+                        if let Some(name) = team_names.get(&order.team) {
+                            order.team.clone_from(name);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to get team names: {e}");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+                }
+            }
             let result = order_status::Entity::find().all(&state.db).await;
 
             match result {
@@ -399,7 +499,9 @@ pub fn router() -> Router<&'static UsrState> {
         .route("/change/order", post(change_order))
         .route("/del/order", delete(cancel_order))
         .route("/update/order", post(update_order))
+        .route_layer(middleware::from_fn_with_state(Scope::ManifestWrite, accounts::require_user))
         .route("/list/order", get(get_orders))
+        .route_layer(middleware::from_fn_with_state(Scope::ManifestRead, accounts::require_scope))
 }
 
 pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
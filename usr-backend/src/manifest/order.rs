@@ -1,8 +1,6 @@
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
 
-use crate::scheduler;
-
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "orders")]
 pub struct Model {
@@ -12,7 +10,8 @@ pub struct Model {
     pub count: u32,
     pub unit_cost: Decimal,
     pub store_in: String,
-    pub team: scheduler::Team,
+    /// Code of a row in `team_info`
+    pub team: String,
     pub reason: String,
     pub vendor: String,
     pub link: String,
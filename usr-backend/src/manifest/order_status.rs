@@ -25,6 +25,9 @@ impl ActiveModelBehavior for ActiveModel {}
 pub enum Status {
     #[sea_orm(string_value = "N")]
     New,
+    /// Approved by the lead of the order's team
+    #[sea_orm(string_value = "A")]
+    Approved,
     #[sea_orm(string_value = "S")]
     Submitted,
     #[sea_orm(string_value = "F")]
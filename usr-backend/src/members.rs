@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, http::StatusCode, middleware, response::{IntoResponse, Response}, routing::{get, post}, Json, Router};
+use sea_orm::{
+    sea_query::{OnConflict, Table}, ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait,
+    DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Schema, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::error;
+
+use crate::{accounts::{self, Scope, User}, audit, backup::backup_db, scheduler, UsrState};
+
+pub mod member;
+
+/// Parses a uID such as "u1234567" into its number
+pub fn parse_uid(uid: &str) -> Option<u32> {
+    check_uid(uid).ok()
+}
+
+/// Parses a uID such as "u1234567" into its number, explaining why it is malformed if it is
+pub fn check_uid(uid: &str) -> Result<u32, &'static str> {
+    let uid = uid.trim();
+    if uid.is_empty() {
+        return Err("uID is empty");
+    }
+    let digits = uid
+        .strip_prefix('u')
+        .or_else(|| uid.strip_prefix('U'))
+        .ok_or("uID must start with u")?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("uID must be u followed by digits");
+    }
+    digits.parse().map_err(|_| "uID is too long")
+}
+
+pub async fn find_by_name(db: &impl ConnectionTrait, name: &str) -> Result<Option<member::Model>, sea_orm::DbErr> {
+    member::Entity::find()
+        .filter(member::Column::Name.eq(name))
+        .one(db)
+        .await
+}
+
+/// Finds a member by name, creating one if nobody has that name yet
+pub async fn find_or_create(db: &impl ConnectionTrait, name: &str) -> Result<member::Model, sea_orm::DbErr> {
+    member::Entity::insert(member::ActiveModel {
+        id: ActiveValue::NotSet,
+        uid: ActiveValue::Set(None),
+        name: ActiveValue::Set(name.to_string()),
+        email: ActiveValue::Set(None),
+    })
+    .on_conflict(OnConflict::column(member::Column::Name).do_nothing().to_owned())
+    .do_nothing()
+    .exec(db)
+    .await?;
+
+    find_by_name(db, name)
+        .await?
+        .ok_or_else(|| sea_orm::DbErr::RecordNotFound(format!("Member {name}")))
+}
+
+/// Maps the ids of every member to their names
+pub async fn names(db: &impl ConnectionTrait) -> Result<HashMap<u32, String>, sea_orm::DbErr> {
+    Ok(member::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.id, model.name))
+        .collect())
+}
+
+/// Finds the member with a uID
+pub async fn find_by_uid(db: &impl ConnectionTrait, uid: u32) -> Result<Option<member::Model>, sea_orm::DbErr> {
+    member::Entity::find()
+        .filter(member::Column::Uid.eq(uid))
+        .one(db)
+        .await
+}
+
+/// Maps uIDs to the members that have them
+pub async fn by_uid(db: &impl ConnectionTrait) -> Result<HashMap<u32, member::Model>, sea_orm::DbErr> {
+    Ok(member::Entity::find()
+        .filter(member::Column::Uid.is_not_null())
+        .all(db)
+        .await?
+        .into_iter()
+        .filter_map(|model| Some((model.uid?, model)))
+        .collect())
+}
+
+#[derive(Serialize)]
+struct MemberInfo {
+    #[serde(flatten)]
+    member: member::Model,
+    /// Names of the teams the member is on in the current term
+    teams: Vec<String>,
+}
+
+#[axum::debug_handler]
+async fn list_members(State(state): State<&'static UsrState>) -> Response {
+    let (members, teams) = tokio::join!(
+        member::Entity::find().order_by_asc(member::Column::Name).all(&state.db),
+        scheduler::member_teams(&state.db, state.time_zone),
+    );
+
+    match (members, teams) {
+        (Ok(members), Ok(mut teams)) => Json(
+            members
+                .into_iter()
+                .map(|member| MemberInfo {
+                    teams: teams.remove(&member.id).unwrap_or_default(),
+                    member,
+                })
+                .collect::<Vec<_>>(),
+        ).into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to list members: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PendingMember {
+    /// Only used when changing a member
+    #[serde(default)]
+    id: u32,
+    name: String,
+    #[serde(default)]
+    uid: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+}
+
+impl PendingMember {
+    /// Checks the member, making sure that nobody else has the same name or uID
+    async fn check(self, db: &impl ConnectionTrait) -> Result<Result<member::Model, &'static str>, sea_orm::DbErr> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Ok(Err("Name is empty"));
+        }
+        let uid = match self.uid.as_deref().filter(|uid| !uid.is_empty()) {
+            Some(uid) => match parse_uid(uid) {
+                Some(uid) => Some(uid),
+                None => return Ok(Err("Invalid uID")),
+            },
+            None => None,
+        };
+        if find_by_name(db, &name).await?.is_some_and(|model| model.id != self.id) {
+            return Ok(Err("Name is taken"));
+        }
+        if let Some(uid) = uid {
+            let existing = member::Entity::find()
+                .filter(member::Column::Uid.eq(uid))
+                .one(db)
+                .await?;
+            if existing.is_some_and(|model| model.id != self.id) {
+                return Ok(Err("uID is taken"));
+            }
+        }
+
+        Ok(Ok(member::Model {
+            id: self.id,
+            uid,
+            name,
+            email: self.email.filter(|email| !email.is_empty()),
+        }))
+    }
+}
+
+#[axum::debug_handler]
+async fn new_member(State(state): State<&'static UsrState>, user: User, Json(pending_member): Json<PendingMember>) -> Response {
+    let model = match pending_member.check(&state.db).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to check member: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let mut active_model: member::ActiveModel = model.into();
+    active_model.id = ActiveValue::NotSet;
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.insert(tx).await?;
+        audit::record(tx, Some(user.id), "new_member", "member", model.id, Value::Null, json!(model)).await?;
+        Ok::<_, sea_orm::DbErr>(model)
+    })).await;
+
+    match result {
+        Ok(model) => {
+            backup_db(state);
+            Json(model).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create member: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Changes the name, uID or email of a member. Renaming a member also
+/// renames them in the invitees of meetings
+#[axum::debug_handler]
+async fn change_member(State(state): State<&'static UsrState>, user: User, Json(pending_member): Json<PendingMember>) -> Response {
+    let old_model = match member::Entity::find_by_id(pending_member.id).one(&state.db).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Member not found").into_response(),
+        Err(e) => {
+            error!("Failed to find member: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let model = match pending_member.check(&state.db).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to check member: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        if old_model.name != model.name {
+            scheduler::rename_member(tx, &old_model.name, &model.name).await?;
+        }
+        let active_model = member::ActiveModel {
+            id: ActiveValue::Unchanged(model.id),
+            uid: ActiveValue::Set(model.uid),
+            name: ActiveValue::Set(model.name),
+            email: ActiveValue::Set(model.email),
+        };
+        let model = active_model.update(tx).await?;
+        audit::record(tx, Some(user.id), "change_member", "member", model.id, json!(old_model), json!(model)).await?;
+        Ok::<_, sea_orm::DbErr>(model)
+    })).await;
+
+    match result {
+        Ok(model) => {
+            backup_db(state);
+            Json(model).into_response()
+        }
+        Err(e) => {
+            error!("Failed to change member: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+pub fn router() -> Router<&'static UsrState> {
+    Router::new()
+        .route("/new/member", post(new_member))
+        .route("/change/member", post(change_member))
+        .route_layer(middleware::from_fn_with_state(Scope::MembersWrite, accounts::require_user))
+        .route("/list/member", get(list_members))
+        .route_layer(middleware::from_fn_with_state(Scope::MembersRead, accounts::require_scope))
+}
+
+pub async fn init_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(schema.create_table_from_entity(member::Entity).if_not_exists()))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(Table::drop().table(member::Entity).if_exists()))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(member::Entity)))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// Someone in the organization, which both the scheduler and attendance refer to
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "members")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    /// Number of the member's uID, which attendance is recorded by.
+    /// Members created by the scheduler do not have one until it is set
+    #[sea_orm(unique)]
+    pub uid: Option<u32>,
+    /// Display name, which the scheduler refers to members by
+    #[sea_orm(unique)]
+    pub name: String,
+    pub email: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,139 @@
+use sea_orm::{sea_query::Table, ConnectionTrait, EntityTrait, IdenStatic, Schema, Statement};
+
+/// Checks if a table created by an older version already has a column
+pub async fn has_column(db: &impl ConnectionTrait, table: &str, column: &str) -> Result<bool, sea_orm::DbErr> {
+    let rows = db
+        .query_all(Statement::from_string(
+            db.get_database_backend(),
+            format!("PRAGMA table_info(\"{table}\")"),
+        ))
+        .await?;
+
+    for row in rows {
+        let name: String = row.try_get("", "name")?;
+        if name == column {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Adds a column that is missing from a table created by an older version.
+/// The column needs a default value if it is not nullable.
+pub async fn add_column<E: EntityTrait>(db: &impl ConnectionTrait, entity: E, column: E::Column) -> Result<(), sea_orm::DbErr> {
+    if has_column(db, entity.table_name(), column.as_str()).await? {
+        return Ok(());
+    }
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(
+        Table::alter()
+            .table(entity)
+            .add_column(schema.get_column_def::<E>(column)),
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Recreates the table of `entity` from its current definition, which is the only way
+/// to change primary keys in SQLite. The old rows are copied over by selecting `select`
+/// from the old table into `columns` of the new one.
+///
+/// This should be called inside of a transaction.
+pub async fn rebuild_table<E: EntityTrait>(
+    db: &impl ConnectionTrait,
+    entity: E,
+    columns: &str,
+    select: &str,
+) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+    let table = entity.table_name();
+
+    db.execute_unprepared(&format!("ALTER TABLE \"{table}\" RENAME TO \"{table}_old\""))
+        .await?;
+    db.execute(builder.build(&schema.create_table_from_entity(entity)))
+        .await?;
+    db.execute_unprepared(&format!(
+        "INSERT INTO \"{table}\" ({columns}) SELECT {select} FROM \"{table}_old\""
+    ))
+    .await?;
+    db.execute_unprepared(&format!("DROP TABLE \"{table}_old\""))
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+    use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+
+    use crate::test_db;
+
+    /// Tables as the first version created them
+    const BASELINE: &str = r#"
+        CREATE TABLE "availabilities" ("name" varchar NOT NULL, "time" integer NOT NULL, PRIMARY KEY ("name", "time"));
+        CREATE TABLE "teams" ("name" varchar NOT NULL, "team" varchar(1) NOT NULL, PRIMARY KEY ("name", "team"));
+        CREATE TABLE "attendance" ("uid" integer NOT NULL, "date" timestamp_text NOT NULL, PRIMARY KEY ("uid", "date"));
+        INSERT INTO "availabilities" VALUES ('Alice', 40), ('Alice', 41), ('Bob', 40);
+        INSERT INTO "teams" VALUES ('Alice', 'C'), ('Bob', 'M');
+        INSERT INTO "attendance" VALUES (1234567, '2026-10-12 09:00:00'), (1234567, '2026-10-12 11:30:00');
+    "#;
+
+    async fn query(db: &DatabaseConnection, sql: &str) -> Vec<String> {
+        db.query_all(Statement::from_string(db.get_database_backend(), sql))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.try_get_by_index(0).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn migrates_baseline() {
+        let db = test_db("migration-baseline").await;
+        db.execute_unprepared(BASELINE).await.unwrap();
+        crate::init_tables(&db, Tz::America__Denver).await.unwrap();
+        // Starting again finds nothing left to migrate
+        crate::init_tables(&db, Tz::America__Denver).await.unwrap();
+
+        assert_eq!(query(&db, "SELECT name FROM terms").await, ["Initial"]);
+        assert_eq!(
+            query(&db, "SELECT members.name || ' ' || time FROM availabilities JOIN members ON members.id = member ORDER BY 1").await,
+            ["Alice 40", "Alice 41", "Bob 40"]
+        );
+        assert_eq!(
+            query(&db, "SELECT members.name || ' ' || team FROM teams JOIN members ON members.id = member ORDER BY 1").await,
+            ["Alice C", "Bob M"]
+        );
+        assert_eq!(
+            query(&db, "SELECT start || ' ' || \"end\" FROM attendance_sessions").await,
+            ["2026-10-12 09:00:00 2026-10-12 11:30:00"]
+        );
+    }
+
+    #[tokio::test]
+    async fn migrates_overrides_with_names() {
+        let db = test_db("migration-overrides").await;
+        db.execute_unprepared(BASELINE).await.unwrap();
+        db.execute_unprepared(r#"
+            CREATE TABLE "availability_overrides" (
+                "name" varchar NOT NULL, "date" date_text NOT NULL, "time" integer NOT NULL, "available" boolean NOT NULL,
+                PRIMARY KEY ("name", "date", "time")
+            );
+            INSERT INTO "availability_overrides" VALUES ('Carol', '2025-09-02', 12, true);
+        "#).await.unwrap();
+        crate::init_tables(&db, Tz::America__Denver).await.unwrap();
+
+        // The initial term has to cover the overrides that were made before terms existed
+        assert_eq!(query(&db, "SELECT start FROM terms").await, ["2025-09-02"]);
+        assert_eq!(
+            query(&db, "SELECT members.name || ' ' || date FROM availability_overrides JOIN members ON members.id = member").await,
+            ["Carol 2025-09-02"]
+        );
+    }
+}
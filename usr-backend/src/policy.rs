@@ -0,0 +1,519 @@
+use std::collections::HashSet;
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use sea_orm::ConnectionTrait;
+use serde::de::DeserializeOwned;
+use tracing::error;
+
+use crate::{accounts::{self, Role, Scope, User}, manifest, scheduler, UsrState};
+
+/// Largest body that is read to find out what a request does. Every such body is a few fields.
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// Something that not every user may do. Every route maps to what it does in `rule`,
+/// and `enforce` checks it before the handler runs, so that every rule about roles lives here.
+pub enum Action {
+    /// Changing the status of an order to anything but approved
+    UpdateOrderStatus,
+    /// Approving an order of the team with the given code
+    ApproveOrder { team: String },
+    /// Cancelling an order that has already been processed
+    ForceCancelOrder,
+    /// Adding a member other than the user themselves to the teams with the given codes,
+    /// or removing them from those teams
+    SetOthersTeams { teams: HashSet<String> },
+    /// Adding, changing and removing attendance by hand
+    CorrectAttendance,
+    /// Creating terms and copying teams between them
+    ManageTerms,
+    /// Creating and retiring teams, and setting the hours they require
+    ManageTeams,
+    /// Creating and changing members
+    ManageMembers,
+    /// Creating users and linking them to members
+    ManageUsers,
+    /// Giving roles to users and taking them away
+    ManageRoles,
+    /// Issuing an API token with the given scopes
+    IssueScopes { scopes: HashSet<Scope> },
+    /// Listing and revoking the API tokens of other users
+    ManageOthersTokens,
+    /// Seeing who changed what
+    ViewAuditLog,
+}
+
+impl Action {
+    /// Explains who may take the action, for when the user may not
+    fn denial(&self) -> &'static str {
+        match self {
+            Action::UpdateOrderStatus => "Only treasurers can change the status of orders",
+            Action::ApproveOrder { .. } => "Only the lead of the order's team can approve it",
+            Action::ForceCancelOrder => "Only admins can cancel processed orders",
+            Action::SetOthersTeams { .. } => "Only leads can set the teams of other people, and only for their own teams",
+            Action::CorrectAttendance => "Only admins can correct attendance",
+            Action::ManageTerms => "Only admins can manage terms",
+            Action::ManageTeams => "Only admins can manage teams",
+            Action::ManageMembers => "Only admins can manage members",
+            Action::ManageUsers => "Only admins can create users",
+            Action::ManageRoles => "Only admins can manage roles",
+            Action::IssueScopes { .. } => "Only admins can issue tokens with the kiosk, members:write or audit scopes",
+            Action::ManageOthersTokens => "Only admins can manage the tokens of other users",
+            Action::ViewAuditLog => "Only admins can see the audit log",
+        }
+    }
+}
+
+/// The roles of a user
+pub struct Roles {
+    admin: bool,
+    treasurer: bool,
+    /// Codes of the teams that the user leads
+    leads: HashSet<String>,
+}
+
+impl Roles {
+    pub async fn load(db: &impl ConnectionTrait, user: u32) -> Result<Self, sea_orm::DbErr> {
+        let mut roles = Self {
+            admin: false,
+            treasurer: false,
+            leads: HashSet::new(),
+        };
+        for model in accounts::roles(db, user).await? {
+            match model.role {
+                Role::Admin => roles.admin = true,
+                Role::Treasurer => roles.treasurer = true,
+                Role::Lead => {
+                    roles.leads.insert(model.team);
+                }
+            }
+        }
+        Ok(roles)
+    }
+
+    /// Admins may do anything, and everyone else needs the role that the action calls for
+    pub fn allows(&self, action: &Action) -> bool {
+        if self.admin {
+            return true;
+        }
+        match action {
+            Action::UpdateOrderStatus => self.treasurer,
+            Action::ApproveOrder { team } => self.leads.contains(team),
+            Action::SetOthersTeams { teams } => !self.leads.is_empty() && teams.is_subset(&self.leads),
+            // Kiosks are trusted more than their users, and only admins can use the rest
+            Action::IssueScopes { scopes } => scopes
+                .iter()
+                .all(|scope| !matches!(scope, Scope::AttendanceKiosk | Scope::MembersWrite | Scope::AuditRead)),
+            Action::ForceCancelOrder
+            | Action::CorrectAttendance
+            | Action::ManageTerms
+            | Action::ManageTeams
+            | Action::ManageMembers
+            | Action::ManageUsers
+            | Action::ManageRoles
+            | Action::ManageOthersTokens
+            | Action::ViewAuditLog => false,
+        }
+    }
+}
+
+/// What a route needs of the user
+enum Rule {
+    /// Nothing beyond what the module's own layers check
+    Open,
+    /// Any logged in user, for routes that show the uIDs or emails of members
+    LoggedIn,
+    /// A user that may always take the action
+    Needs(Action),
+    /// A user that may take whatever `update_order` action the body asks for
+    UpdateOrder,
+    /// A user that may force the cancellation, if the body asks for it
+    CancelOrder,
+    /// A user that may set the teams of the member in the body, unless it is their own member
+    SetTeams,
+    /// A user that may issue a token with the scopes in the body
+    NewToken,
+    /// A user that may revoke the token in the body, unless they issued it
+    DeleteToken,
+}
+
+/// Maps every route to what it needs. Routes that are missing are refused,
+/// so a new route has to be added here before it can be used.
+fn rule(path: &str) -> Option<Rule> {
+    let rule = match path {
+        "/api/scheduler/set/team" => Rule::SetTeams,
+        "/api/scheduler/copy/team" | "/api/scheduler/new/term" => Rule::Needs(Action::ManageTerms),
+        "/api/scheduler/new/team" | "/api/scheduler/del/team" | "/api/scheduler/set/requirement" => {
+            Rule::Needs(Action::ManageTeams)
+        }
+        "/api/scheduler/add/schedule"
+        | "/api/scheduler/del/schedule"
+        | "/api/scheduler/add/override"
+        | "/api/scheduler/del/override"
+        | "/api/scheduler/new/meeting"
+        | "/api/scheduler/change/meeting"
+        | "/api/scheduler/del/meeting"
+        | "/api/scheduler/get/schedule"
+        | "/api/scheduler/get/week"
+        | "/api/scheduler/get/heatmap"
+        | "/api/scheduler/list/term"
+        | "/api/scheduler/list/meeting"
+        | "/api/scheduler/suggest/meeting"
+        | "/api/scheduler/ics/team/{file}"
+        | "/api/scheduler/ics/member/{file}"
+        | "/api/scheduler/list/team" => Rule::Open,
+
+        "/api/manifest/update/order" => Rule::UpdateOrder,
+        "/api/manifest/del/order" => Rule::CancelOrder,
+        "/api/manifest/new/order" | "/api/manifest/change/order" | "/api/manifest/list/order" => Rule::Open,
+
+        "/api/attendance/new/attendance" | "/api/attendance/change/attendance" | "/api/attendance/del/attendance" => {
+            Rule::Needs(Action::CorrectAttendance)
+        }
+        "/api/attendance/new/event"
+        | "/api/attendance/change/event"
+        | "/api/attendance/del/event"
+        | "/api/attendance/mark/present"
+        | "/api/attendance/del/present"
+        | "/api/attendance/del/card"
+        | "/api/attendance/get/headcount" => Rule::Open,
+        // Kiosks have no user, so their handlers check for a kiosk key, token or check-in token
+        "/api/attendance/add/attendance" | "/api/attendance/register/card" | "/api/attendance/get/token" => Rule::Open,
+        "/api/attendance/get/occupancy"
+        | "/api/attendance/stream/occupancy"
+        | "/api/attendance/list/attendance"
+        | "/api/attendance/get/daily"
+        | "/api/attendance/get/members"
+        | "/api/attendance/list/correction"
+        | "/api/attendance/export/attendance"
+        | "/api/attendance/list/session"
+        | "/api/attendance/get/hours"
+        | "/api/attendance/get/compliance"
+        | "/api/attendance/get/leaderboard"
+        | "/api/attendance/list/event"
+        | "/api/attendance/get/roster"
+        | "/api/attendance/list/card" => Rule::LoggedIn,
+
+        "/api/members/new/member" | "/api/members/change/member" => Rule::Needs(Action::ManageMembers),
+        "/api/members/list/member" => Rule::LoggedIn,
+
+        "/api/accounts/new/user" => Rule::Needs(Action::ManageUsers),
+        "/api/accounts/set/role" | "/api/accounts/del/role" => Rule::Needs(Action::ManageRoles),
+        "/api/accounts/new/token" => Rule::NewToken,
+        "/api/accounts/del/token" => Rule::DeleteToken,
+        "/api/accounts/change/password"
+        | "/api/accounts/list/user"
+        | "/api/accounts/list/token"
+        | "/api/accounts/login"
+        | "/api/accounts/logout"
+        | "/api/accounts/login/oidc"
+        | "/api/accounts/callback/oidc"
+        | "/api/accounts/get/user" => Rule::Open,
+
+        "/api/audit/list/entry" => Rule::Needs(Action::ViewAuditLog),
+
+        _ => return None,
+    };
+    Some(rule)
+}
+
+/// Parses a body into the type that the handler extracts. The handler refuses bodies
+/// that do not parse, explaining why, so those need nothing here.
+fn parse<T: DeserializeOwned>(body: &Bytes) -> Option<T> {
+    Json::<T>::from_bytes(body).ok().map(|Json(x)| x)
+}
+
+impl Rule {
+    /// Finds the action that a request with `body` takes, if it takes one
+    async fn action(self, state: &'static UsrState, user: &User, body: &Bytes) -> Result<Option<Action>, sea_orm::DbErr> {
+        let db = &state.db;
+        let action = match self {
+            Rule::Open | Rule::LoggedIn => None,
+            Rule::Needs(action) => Some(action),
+            Rule::UpdateOrder => {
+                let Some(update) = parse::<manifest::UpdateOrder>(body) else {
+                    return Ok(None);
+                };
+                match manifest::order_team(db, update.id).await? {
+                    // The handler refuses orders that do not exist
+                    None => None,
+                    Some(team) if update.approves() => Some(Action::ApproveOrder { team }),
+                    Some(_) => Some(Action::UpdateOrderStatus),
+                }
+            }
+            Rule::CancelOrder => {
+                parse::<manifest::DeleteOrder>(body)
+                    .filter(|cancel| cancel.force)
+                    .map(|_| Action::ForceCancelOrder)
+            }
+            Rule::SetTeams => {
+                let Some(set_team) = parse::<scheduler::SetTeam>(body) else {
+                    return Ok(None);
+                };
+                match set_team.changes(db, state.time_zone).await? {
+                    (Some(member), _) if user.member == Some(member.id) => None,
+                    (_, teams) => Some(Action::SetOthersTeams { teams }),
+                }
+            }
+            Rule::NewToken => {
+                parse::<accounts::NewToken>(body).map(|new_token| Action::IssueScopes { scopes: new_token.scopes })
+            }
+            Rule::DeleteToken => {
+                let Some(delete) = parse::<accounts::DeleteToken>(body) else {
+                    return Ok(None);
+                };
+                match accounts::token_owner(db, delete.id).await? {
+                    Some(owner) if owner != user.id => Some(Action::ManageOthersTokens),
+                    // The handler refuses tokens that do not exist
+                    _ => None,
+                }
+            }
+        };
+        Ok(action)
+    }
+}
+
+/// Checks that a user may take an action, returning the response to reject the request with if not
+async fn authorize(db: &impl ConnectionTrait, user: &User, action: Action) -> Result<(), (StatusCode, &'static str)> {
+    match Roles::load(db, user.id).await {
+        Ok(roles) if roles.allows(&action) => Ok(()),
+        Ok(_) => Err((StatusCode::FORBIDDEN, action.denial())),
+        Err(e) => {
+            error!("Failed to load roles: {e}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, ""))
+        }
+    }
+}
+
+/// Refuses requests whose user may not take the action of their route.
+/// This should be added with `layer` so that it sees the matched route, and relies on
+/// `load_user` running first.
+pub async fn enforce(State(state): State<&'static UsrState>, request: Request, next: Next) -> Response {
+    let Some(path) = request.extensions().get::<MatchedPath>() else {
+        // Nothing matched, so there is no rule to check and nothing is let through
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(rule) = rule(path.as_str()) else {
+        error!("No policy for {}", path.as_str());
+        return (StatusCode::FORBIDDEN, "").into_response();
+    };
+    if let Rule::Open = rule {
+        return next.run(request).await;
+    }
+    let Some(user) = request.extensions().get::<User>().cloned() else {
+        return (StatusCode::UNAUTHORIZED, "Not logged in").into_response();
+    };
+    if let Rule::LoggedIn = rule {
+        return next.run(request).await;
+    }
+
+    // Only some rules look at the body, but it is small enough to always read
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_BODY_LEN).await {
+        Ok(x) => x,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "").into_response(),
+    };
+    let action = match rule.action(state, &user, &body).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to find the action of a request: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    if let Some(action) = action {
+        if let Err(response) = authorize(&state.db, &user, action).await {
+            return response.into_response();
+        }
+    }
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, http::header, middleware, routing::get, Router};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Sends requests through the same router, `load_user` and `enforce` as the server
+    struct App {
+        state: &'static UsrState,
+        router: Router,
+    }
+
+    impl App {
+        async fn new(name: &str) -> Self {
+            let db = crate::test_db(&format!("policy-{name}")).await;
+            crate::init_tables(&db, chrono_tz::America::Denver).await.unwrap();
+            // The manifest only creates its tables when reset
+            manifest::reset_tables(&db).await.unwrap();
+            let state = UsrState::for_tests(db, vec!["lab-kiosk".into()]);
+            let router = Router::new().nest("/api", crate::api_router(state)).with_state(state);
+            Self { state, router }
+        }
+
+        async fn user(&self, username: &str, roles: &[(Role, &str)]) -> String {
+            accounts::test_user(&self.state.db, username, roles).await
+        }
+
+        async fn send(&self, request: axum::http::request::Builder, body: Body) -> (StatusCode, String) {
+            let response = self.router.clone().oneshot(request.body(body).unwrap()).await.unwrap();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        }
+
+        async fn get(&self, uri: &str, cookie: Option<&str>) -> (StatusCode, String) {
+            self.send(with_cookie(Request::get(uri), cookie), Body::empty()).await
+        }
+
+        async fn post(&self, uri: &str, cookie: Option<&str>, body: Value) -> (StatusCode, String) {
+            let request = with_cookie(Request::post(uri), cookie).header(header::CONTENT_TYPE, "application/json");
+            self.send(request, Body::from(body.to_string())).await
+        }
+    }
+
+    fn with_cookie(request: axum::http::request::Builder, cookie: Option<&str>) -> axum::http::request::Builder {
+        match cookie {
+            Some(cookie) => request.header(header::COOKIE, cookie),
+            None => request,
+        }
+    }
+
+    fn order() -> Value {
+        json!({
+            "name": "Motor", "count": 2, "unit_cost": "12.50", "store_in": "Lab", "team": "C",
+            "reason": "Drive", "vendor": "Store", "link": "https://example.com",
+        })
+    }
+
+    #[tokio::test]
+    async fn admin() {
+        let app = App::new("admin").await;
+        let admin = app.user("admin", &[(Role::Admin, "")]).await;
+        let team = json!({ "code": "R", "name": "Research", "color": "#123456" });
+        assert_eq!(app.post("/api/scheduler/new/team", Some(&admin), team).await.0, StatusCode::OK);
+        assert_eq!(app.get("/api/audit/list/entry", Some(&admin)).await.0, StatusCode::OK);
+        assert_eq!(app.post("/api/members/new/member", Some(&admin), json!({ "name": "Bob" })).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn treasurer() {
+        let app = App::new("treasurer").await;
+        let treasurer = app.user("treasurer", &[(Role::Treasurer, "")]).await;
+        assert_eq!(app.post("/api/manifest/new/order", Some(&treasurer), order()).await.0, StatusCode::OK);
+
+        let approve = json!({ "id": 1, "status": "Approved" });
+        assert_eq!(
+            app.post("/api/manifest/update/order", Some(&treasurer), approve).await,
+            (StatusCode::FORBIDDEN, "Only the lead of the order's team can approve it".into())
+        );
+        let submit = json!({ "id": 1, "status": "Submitted" });
+        assert_eq!(app.post("/api/manifest/update/order", Some(&treasurer), submit).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn lead() {
+        let app = App::new("lead").await;
+        let lead = app.user("lead", &[(Role::Lead, "C")]).await;
+
+        let own_team = json!({ "name": "Bob", "teams": ["C"] });
+        assert_eq!(app.post("/api/scheduler/set/team", Some(&lead), own_team).await.0, StatusCode::OK);
+        let other_team = json!({ "name": "Bob", "teams": ["C", "M"] });
+        assert_eq!(
+            app.post("/api/scheduler/set/team", Some(&lead), other_team).await,
+            (StatusCode::FORBIDDEN, Action::SetOthersTeams { teams: HashSet::new() }.denial().into())
+        );
+
+        assert_eq!(app.post("/api/manifest/new/order", Some(&lead), order()).await.0, StatusCode::OK);
+        let approve = json!({ "id": 1, "status": "Approved" });
+        assert_eq!(app.post("/api/manifest/update/order", Some(&lead), approve).await.0, StatusCode::OK);
+        let submit = json!({ "id": 1, "status": "Submitted" });
+        assert_eq!(
+            app.post("/api/manifest/update/order", Some(&lead), submit).await,
+            (StatusCode::FORBIDDEN, "Only treasurers can change the status of orders".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn member() {
+        let app = App::new("member").await;
+        let member = app.user("member", &[]).await;
+        assert_eq!(app.get("/api/members/list/member", Some(&member)).await.0, StatusCode::OK);
+        assert_eq!(
+            app.post("/api/members/new/member", Some(&member), json!({ "name": "Bob" })).await,
+            (StatusCode::FORBIDDEN, "Only admins can manage members".into())
+        );
+        assert_eq!(
+            app.get("/api/audit/list/entry", Some(&member)).await,
+            (StatusCode::FORBIDDEN, "Only admins can see the audit log".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn anonymous() {
+        let app = App::new("anonymous").await;
+        assert_eq!(app.get("/api/scheduler/list/team", None).await.0, StatusCode::OK);
+        for uri in ["/api/members/list/member", "/api/attendance/export/attendance", "/api/attendance/get/leaderboard"] {
+            assert_eq!(app.get(uri, None).await, (StatusCode::UNAUTHORIZED, "Not logged in".into()), "{uri}");
+        }
+        // A session that does not exist is no better than none
+        assert_eq!(app.get("/api/members/list/member", Some("usr_session=0000")).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn kiosk() {
+        let app = App::new("kiosk").await;
+        let check_in = |key: &str| {
+            Request::post("/api/attendance/add/attendance")
+                .header("X-Kiosk-Key", key)
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        };
+        // Check-ins have no user, so the handler checks the kiosk key instead of the policy
+        assert_eq!(app.send(check_in("lab-kiosk"), Body::from("uid=u1234567")).await.0, StatusCode::OK);
+        assert_eq!(
+            app.send(check_in("lab"), Body::from("uid=u1234567")).await,
+            (StatusCode::BAD_REQUEST, "Missing check-in token".into())
+        );
+
+        let card = json!({ "card": "12345678", "uid": "u1234567" });
+        assert_eq!(app.post("/api/attendance/register/card", None, card).await, (StatusCode::UNAUTHORIZED, "Not logged in".into()));
+        let register = Request::post("/api/attendance/register/card")
+            .header("X-Kiosk-Key", "lab-kiosk")
+            .header(header::CONTENT_TYPE, "application/json");
+        let card = json!({ "card": "12345678", "uid": "u1234567" });
+        assert_eq!(app.send(register, Body::from(card.to_string())).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unknown_routes() {
+        let app = App::new("unknown").await;
+        let admin = app.user("admin", &[(Role::Admin, "")]).await;
+        assert_eq!(app.get("/api/scheduler/nothing", Some(&admin)).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(app.post("/api/nothing", None, json!({})).await.0, StatusCode::NOT_FOUND);
+
+        // A route that was added without a rule is refused, even for admins, and so is a fallback
+        let state = app.state;
+        let router = Router::new()
+            .nest(
+                "/api",
+                Router::new()
+                    .route("/unlisted", get(|| async { "Unlisted" }))
+                    .fallback(|| async { "Fallback" })
+                    .layer(middleware::from_fn_with_state(state, enforce))
+                    .layer(middleware::from_fn_with_state(state, accounts::load_user)),
+            )
+            .with_state(state);
+        let app = App { state, router };
+        assert_eq!(app.get("/api/unlisted", Some(&admin)).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(app.get("/api/unlisted", None).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(app.get("/api/elsewhere", Some(&admin)).await.0, StatusCode::NOT_FOUND);
+    }
+}
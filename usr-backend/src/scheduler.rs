@@ -1,64 +1,816 @@
-use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::{collections::{hash_map::Entry, BTreeSet, HashMap, HashSet}, sync::Arc};
 
-use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, routing::{delete, get, post}, Json, Router};
-use sea_orm::{sea_query::Table, ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, Schema, TransactionTrait};
+use axum::{extract::{Path, Query, State}, http::{header, StatusCode}, middleware, response::{IntoResponse, Response}, routing::{delete, get, post}, Json, Router};
+use chrono::{Datelike, Days, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use discord_webhook2::webhook::DiscordWebhook;
+use sea_orm::{prelude::Date, sea_query::{OnConflict, Table}, ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Schema, TransactionTrait};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tracing::error;
 
-use crate::{backup::backup_db, UsrState};
+use crate::{accounts::{self, Scope, User}, audit, backup::backup_db, members, migration, webhook::BatchedWebhook, UsrState};
 
 mod availability;
+mod availability_override;
+mod ical;
+mod meeting;
 mod team;
+pub mod team_info;
+mod team_query;
+mod term;
+mod zone;
 
-pub use team::Team;
+use availability::Preference;
+use team_query::TeamQuery;
+use zone::Zone;
+
+/// Number of 15 minute slots in a day, which runs from 9 AM to 7 PM
+pub const SLOTS_PER_DAY: u16 = 10 * 4;
+/// Number of 15 minute slots in a week, which starts on Monday
+pub const SLOTS_PER_WEEK: u16 = 7 * SLOTS_PER_DAY;
+/// Hour of the day that the first slot of each day starts at
+pub const DAY_START_HOUR: u32 = 9;
+
+/// Splits a slot of the week into the day of the week, where 0 is Monday,
+/// and the time of day that the slot starts at
+pub fn slot_time(slot: u16) -> (u16, NaiveTime) {
+    let minutes = (slot % SLOTS_PER_DAY) as u32 * 15;
+    (
+        slot / SLOTS_PER_DAY,
+        NaiveTime::from_hms_opt(DAY_START_HOUR + minutes / 60, minutes % 60, 0).unwrap(),
+    )
+}
+
+/// The teams that used to be hard-coded, as (code, name, color)
+const LEGACY_TEAMS: [(&str, &str, &str); 6] = [
+    ("C", "Software", "#3b82f6"),
+    ("M", "Mechanical", "#ef4444"),
+    ("E", "Electrical", "#eab308"),
+    ("S", "Systems", "#22c55e"),
+    ("G", "Social", "#ec4899"),
+    ("A", "Admin", "#6b7280"),
+];
+
+/// Finds a team that is not retired by either its code or its name
+pub async fn find_team(db: &impl ConnectionTrait, team: &str) -> Result<Option<team_info::Model>, sea_orm::DbErr> {
+    team_info::Entity::find()
+        .filter(team_info::Column::Retired.eq(false))
+        .filter(
+            Condition::any()
+                .add(team_info::Column::Code.eq(team))
+                .add(team_info::Column::Name.eq(team)),
+        )
+        .one(db)
+        .await
+}
+
+/// Maps team codes to their names, including retired teams
+pub async fn team_names(db: &impl ConnectionTrait) -> Result<HashMap<String, String>, sea_orm::DbErr> {
+    Ok(team_info::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.code, model.name))
+        .collect())
+}
+
+/// Gets the name of a team, falling back to the code if the team is unknown
+pub async fn team_name(db: &impl ConnectionTrait, code: &str) -> Result<String, sea_orm::DbErr> {
+    Ok(team_info::Entity::find_by_id(code)
+        .one(db)
+        .await?
+        .map(|model| model.name)
+        .unwrap_or_else(|| code.to_string()))
+}
+
+pub fn team_webhook(state: &'static UsrState, code: &str) -> Option<Arc<BatchedWebhook>> {
+    state.team_webhooks.read().get(code).cloned()
+}
+
+/// Gets the term in effect on the given date, which is the latest term to have started
+async fn term_on(db: &impl ConnectionTrait, date: Date) -> Result<Option<term::Model>, sea_orm::DbErr> {
+    term::Entity::find()
+        .filter(term::Column::Start.lte(date))
+        .order_by_desc(term::Column::Start)
+        .one(db)
+        .await
+}
+
+/// Finds the term with the given id, or the current term if there is no id
+async fn find_term(db: &impl ConnectionTrait, time_zone: Tz, id: Option<u32>) -> Result<Option<term::Model>, sea_orm::DbErr> {
+    match id {
+        Some(id) => term::Entity::find_by_id(id).one(db).await,
+        None => term_on(db, Utc::now().with_timezone(&time_zone).date_naive()).await,
+    }
+}
+
+/// Finds the term that a change applies to, which defaults to the current term.
+/// Past terms are read-only, so they are refused with a reason
+async fn writable_term(db: &impl ConnectionTrait, time_zone: Tz, id: Option<u32>) -> Result<Result<term::Model, &'static str>, sea_orm::DbErr> {
+    let Some(current) = find_term(db, time_zone, None).await? else {
+        return Ok(Err("No term has started"));
+    };
+    let Some(id) = id else {
+        return Ok(Ok(current));
+    };
+    let Some(term) = term::Entity::find_by_id(id).one(db).await? else {
+        return Ok(Err("Term not found"));
+    };
+    if term.start < current.start {
+        return Ok(Err("Past terms are read-only"));
+    }
+    Ok(Ok(term))
+}
+
+/// Today in the organization's time zone
+fn today(state: &'static UsrState) -> Date {
+    Utc::now().with_timezone(&state.time_zone).date_naive()
+}
+
+/// Sets up conversions for a client that wants times in its own time zone, `tz`,
+/// using the offsets of the week of `date`. Clients that do not send a time zone
+/// use slots in the organization's time zone.
+fn client_zone(state: &'static UsrState, tz: Option<&str>, date: Date) -> Result<Option<Zone>, &'static str> {
+    let Some(tz) = tz else {
+        return Ok(None);
+    };
+    let client = tz.parse().map_err(|_| "Unknown time zone")?;
+    Ok(Some(Zone::new(state.time_zone, client, date)))
+}
+
+/// Converts slots of the week sent by a client into the organization's time zone
+fn org_slots(zone: Option<&Zone>, times: Box<[u16]>) -> Result<Box<[u16]>, &'static str> {
+    let Some(zone) = zone else {
+        return Ok(times);
+    };
+    times
+        .iter()
+        .map(|&time| zone.slot_from_client(time).ok_or("Time is outside of scheduling hours"))
+        .collect()
+}
+
+/// Converts slots within a date sent by a client into dates and slots in the organization's time zone
+fn org_day_slots(zone: Option<&Zone>, date: Date, times: &[u16]) -> Result<Vec<(Date, u16)>, &'static str> {
+    let Some(zone) = zone else {
+        if times.iter().any(|&time| time >= SLOTS_PER_DAY) {
+            return Err("Time is outside of the day");
+        }
+        return Ok(times.iter().map(|&time| (date, time)).collect());
+    };
+    times
+        .iter()
+        .map(|&time| {
+            if time >= zone::CLIENT_SLOTS_PER_DAY {
+                return Err("Time is outside of the day");
+            }
+            zone.day_from_client(date, time).ok_or("Time is outside of scheduling hours")
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct TermQuery {
+    /// Defaults to the current term
+    term: Option<u32>,
+    /// Time zone to return times in, instead of the organization's
+    tz: Option<String>,
+}
 
 #[derive(Deserialize)]
 struct PendingSchedule {
     name: String,
     times: Box<[u16]>,
+    /// Only used when adding, where slots that were already added take the new preference
+    #[serde(default)]
+    preference: Preference,
+    /// Time zone of `times`, which makes them count from 12 AM Monday in that time zone
+    #[serde(default)]
+    tz: Option<String>,
+    /// Defaults to the current term
+    #[serde(default)]
+    term: Option<u32>,
 }
 
 #[axum::debug_handler]
-async fn add_schedule(State(state): State<&'static UsrState>, Json(pending_schedule): Json<PendingSchedule>) -> (StatusCode, &'static str) {
+async fn add_schedule(State(state): State<&'static UsrState>, user: User, Json(pending_schedule): Json<PendingSchedule>) -> (StatusCode, &'static str) {
     if pending_schedule.name.is_empty() {
         return (StatusCode::BAD_REQUEST, "");
     }
+    if pending_schedule.times.iter().any(|&time| time >= SLOTS_PER_WEEK) && pending_schedule.tz.is_none() {
+        return (StatusCode::BAD_REQUEST, "Time is outside of the week");
+    }
+    let times = match client_zone(state, pending_schedule.tz.as_deref(), today(state)) {
+        Ok(zone) => match org_slots(zone.as_ref(), pending_schedule.times) {
+            Ok(x) => x,
+            Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+        },
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+    };
+    let term = match writable_term(&state.db, state.time_zone, pending_schedule.term).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let term_id = term.id;
+    let name = pending_schedule.name.clone();
     let result = state.db.transaction(|tx| Box::pin(async move {
-        for time in pending_schedule.times {
+        let member = members::find_or_create(tx, &pending_schedule.name).await?;
+        let existing: BTreeSet<u16> = availability::Entity::find()
+            .filter(availability::Column::Term.eq(term_id))
+            .filter(availability::Column::Member.eq(member.id))
+            .all(tx)
+            .await?
+            .into_iter()
+            .map(|model| model.time)
+            .collect();
+        let before = json!({ "term": term_id, "times": existing });
+        let after: BTreeSet<u16> = existing.iter().chain(&times).copied().collect();
+        let after = json!({ "term": term_id, "times": after, "preference": pending_schedule.preference });
+        let mut added = vec![];
+        for time in times {
+            if !existing.contains(&time) {
+                added.push(time);
+            }
             availability::Entity::insert(availability::ActiveModel {
-                name: ActiveValue::Set(pending_schedule.name.clone()),
+                term: ActiveValue::Set(term_id),
+                member: ActiveValue::Set(member.id),
                 time: ActiveValue::Set(time),
-            }).on_conflict_do_nothing().exec(tx).await?;
+                preference: ActiveValue::Set(pending_schedule.preference),
+            })
+            .on_conflict(
+                OnConflict::columns([
+                    availability::Column::Term,
+                    availability::Column::Member,
+                    availability::Column::Time,
+                ])
+                .update_column(availability::Column::Preference)
+                .to_owned(),
+            )
+            .exec(tx)
+            .await?;
         }
-        Result::<_, sea_orm::DbErr>::Ok(())
+        audit::record(tx, Some(user.id), "add_schedule", "availability", member.id, before, after).await?;
+        Result::<_, sea_orm::DbErr>::Ok(added)
     })).await;
     
-    if let Err(e) = result {
-        error!("Failed to insert schedule: {e}");
-        (StatusCode::INTERNAL_SERVER_ERROR, "")
-    } else {
-        backup_db(state);
-        (StatusCode::OK, "")
+    match result {
+        Ok(added) => {
+            backup_db(state);
+            queue_digest(state, &term, name, Change::Added(added));
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to insert schedule: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
     }
 }
 
 #[axum::debug_handler]
-async fn del_schedule(State(state): State<&'static UsrState>, Json(pending_schedule): Json<PendingSchedule>) -> (StatusCode, &'static str) {
+async fn del_schedule(State(state): State<&'static UsrState>, user: User, Json(pending_schedule): Json<PendingSchedule>) -> (StatusCode, &'static str) {
     if pending_schedule.name.is_empty() {
         return (StatusCode::BAD_REQUEST, "");
     }
+    let times = match client_zone(state, pending_schedule.tz.as_deref(), today(state)) {
+        Ok(zone) => match org_slots(zone.as_ref(), pending_schedule.times) {
+            Ok(x) => x,
+            Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+        },
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+    };
+    let term = match writable_term(&state.db, state.time_zone, pending_schedule.term).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let term_id = term.id;
+    let name = pending_schedule.name.clone();
     let result = state.db.transaction(|tx| Box::pin(async move {
-        for time in pending_schedule.times {
-            availability::Entity::delete(availability::ActiveModel {
-                name: ActiveValue::Unchanged(pending_schedule.name.clone()),
+        let mut removed = vec![];
+        let Some(member) = members::find_by_name(tx, &pending_schedule.name).await? else {
+            return Ok(removed);
+        };
+        let existing: BTreeSet<u16> = availability::Entity::find()
+            .filter(availability::Column::Term.eq(term_id))
+            .filter(availability::Column::Member.eq(member.id))
+            .all(tx)
+            .await?
+            .into_iter()
+            .map(|model| model.time)
+            .collect();
+        for time in times {
+            let result = availability::Entity::delete(availability::ActiveModel {
+                term: ActiveValue::Unchanged(term_id),
+                member: ActiveValue::Unchanged(member.id),
                 time: ActiveValue::Unchanged(time),
+                preference: ActiveValue::NotSet,
             }).exec(tx).await?;
+            if result.rows_affected > 0 {
+                removed.push(time);
+            }
         }
-        Result::<_, sea_orm::DbErr>::Ok(())
+        let after: BTreeSet<u16> = existing.iter().filter(|time| !removed.contains(time)).copied().collect();
+        let before = json!({ "term": term_id, "times": existing });
+        let after = json!({ "term": term_id, "times": after });
+        audit::record(tx, Some(user.id), "del_schedule", "availability", member.id, before, after).await?;
+        Result::<_, sea_orm::DbErr>::Ok(removed)
+    })).await;
+    
+    match result {
+        Ok(removed) => {
+            backup_db(state);
+            queue_digest(state, &term, name, Change::Removed(removed));
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to delete schedule: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetTeam {
+    pub name: String,
+    pub teams: HashSet<String>,
+    /// Defaults to the current term
+    #[serde(default)]
+    pub term: Option<u32>,
+}
+
+impl SetTeam {
+    /// Finds the member whose teams are set, if they exist yet, along with the codes of the teams
+    /// that they would join or leave. Teams that are not found are kept as they were given.
+    pub async fn changes(&self, db: &impl ConnectionTrait, time_zone: Tz) -> Result<(Option<members::member::Model>, HashSet<String>), sea_orm::DbErr> {
+        let mut new_codes = HashSet::with_capacity(self.teams.len());
+        for team in &self.teams {
+            match find_team(db, team).await? {
+                Some(model) => new_codes.insert(model.code),
+                None => new_codes.insert(team.clone()),
+            };
+        }
+        let member = members::find_by_name(db, &self.name).await?;
+        let term = writable_term(db, time_zone, self.term).await?;
+        let old_codes = match (&member, term) {
+            (Some(member), Ok(term)) => team::Entity::find()
+                .filter(team::Column::Term.eq(term.id))
+                .filter(team::Column::Member.eq(member.id))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|model| model.team)
+                .collect(),
+            _ => HashSet::new(),
+        };
+        let changed = new_codes.symmetric_difference(&old_codes).cloned().collect();
+        Ok((member, changed))
+    }
+}
+
+#[axum::debug_handler]
+async fn set_teams(State(state): State<&'static UsrState>, user: User, Json(set_team): Json<SetTeam>) -> (StatusCode, &'static str) {
+    if set_team.name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "");
+    }
+    let term = match writable_term(&state.db, state.time_zone, set_team.term).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let mut codes = Vec::with_capacity(set_team.teams.len());
+    for team in &set_team.teams {
+        match find_team(&state.db, team).await {
+            Ok(Some(model)) => codes.push(model.code),
+            Ok(None) => return (StatusCode::BAD_REQUEST, "Unknown team"),
+            Err(e) => {
+                error!("Failed to find team: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "");
+            }
+        }
+    }
+    let term_id = term.id;
+    let name = set_team.name.clone();
+    let new_codes: BTreeSet<String> = codes.iter().cloned().collect();
+    let after = json!({ "term": term_id, "teams": new_codes });
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let member = members::find_or_create(tx, &set_team.name).await?;
+        let old_codes: BTreeSet<String> = team::Entity::find()
+            .filter(team::Column::Term.eq(term_id))
+            .filter(team::Column::Member.eq(member.id))
+            .all(tx)
+            .await?
+            .into_iter()
+            .map(|model| model.team)
+            .collect();
+        team::Entity::delete_many()
+            .filter(team::Column::Term.eq(term_id))
+            .filter(team::Column::Member.eq(member.id))
+            .exec(tx)
+            .await?;
+        for code in codes {
+            let active_model = team::ActiveModel {
+                term: ActiveValue::Set(term_id),
+                member: ActiveValue::Set(member.id),
+                team: ActiveValue::Set(code)
+            };
+            active_model.insert(tx).await?;
+        }
+        let before = json!({ "term": term_id, "teams": old_codes });
+        audit::record(tx, Some(user.id), "set_teams", "member_teams", member.id, before, after).await?;
+        Result::<_, sea_orm::DbErr>::Ok(old_codes)
     })).await;
     
+    match result {
+        Ok(old_codes) => {
+            backup_db(state);
+            match team_names(&state.db).await {
+                Ok(team_names) => {
+                    let names = |codes: BTreeSet<String>| -> BTreeSet<String> {
+                        codes.into_iter().map(|code| team_names.get(&code).cloned().unwrap_or(code)).collect()
+                    };
+                    queue_digest(state, &term, name, Change::Teams(names(old_codes), names(new_codes)));
+                }
+                Err(e) => error!("Failed to get team names: {e}"),
+            }
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to set teams: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+/// A change to the availability or teams of a member
+enum Change {
+    Added(Vec<u16>),
+    Removed(Vec<u16>),
+    /// Team names before and after
+    Teams(BTreeSet<String>, BTreeSet<String>),
+}
+
+/// Changes that a member made in a term since their last digest was sent
+#[derive(Default)]
+struct MemberChanges {
+    added: BTreeSet<u16>,
+    removed: BTreeSet<u16>,
+    /// Team names before the first change and after the last one
+    teams: Option<(BTreeSet<String>, BTreeSet<String>)>,
+}
+
+impl MemberChanges {
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Added(times) => {
+                for time in times {
+                    if !self.removed.remove(&time) {
+                        self.added.insert(time);
+                    }
+                }
+            }
+            Change::Removed(times) => {
+                for time in times {
+                    if !self.added.remove(&time) {
+                        self.removed.insert(time);
+                    }
+                }
+            }
+            Change::Teams(old, new) => match &mut self.teams {
+                Some((_, current)) => *current = new,
+                None => self.teams = Some((old, new)),
+            },
+        }
+    }
+
+    fn summary(&self, name: &str, term: &str) -> String {
+        let mut parts = vec![];
+        if !self.added.is_empty() {
+            parts.push(format!("added {}", slot_ranges(&self.added)));
+        }
+        if !self.removed.is_empty() {
+            parts.push(format!("removed {}", slot_ranges(&self.removed)));
+        }
+        if let Some((old, new)) = &self.teams {
+            let joined: Vec<&str> = new.difference(old).map(String::as_str).collect();
+            let left: Vec<&str> = old.difference(new).map(String::as_str).collect();
+            if !joined.is_empty() {
+                parts.push(format!("joined {}", joined.join(", ")));
+            }
+            if !left.is_empty() {
+                parts.push(format!("left {}", left.join(", ")));
+            }
+        }
+        if parts.is_empty() {
+            format!("**{name}** ({term}) undid their changes")
+        } else {
+            format!("**{name}** ({term}) {}", parts.join(", "))
+        }
+    }
+}
+
+/// Changes waiting to be sent to the scheduler webhook, by term id and member name
+#[derive(Default)]
+pub struct Digests {
+    members: HashMap<(u32, String), (u32, MemberChanges)>,
+    /// Id of the next member to have changes queued, which is never reused while
+    /// an older digest could still be waiting
+    next_id: u32,
+}
+
+/// Forgets the changes of members once their digests are sent
+pub fn watch_digests(state: &'static UsrState) {
+    if let Some(scheduler_webhook) = &state.scheduler_webhook {
+        scheduler_webhook.on_flush(|ids| {
+            state.scheduler_digests.lock().members.retain(|_, (id, _)| !ids.contains(id));
+        });
+    }
+}
+
+/// Formats slots as ranges, eg. "Mon 9:00–11:00, Wed 14:00"
+fn slot_ranges(slots: &BTreeSet<u16>) -> String {
+    let mut ranges: Vec<(u16, u16)> = vec![];
+    for &slot in slots {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == slot && slot % SLOTS_PER_DAY != 0 => *end = slot,
+            _ => ranges.push((slot, slot)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let (day, start_time) = slot_time(start);
+            let day = &DAY_NAMES[day as usize][..3];
+            if start == end {
+                format!("{day} {}", start_time.format("%-H:%M"))
+            } else {
+                let end_time = slot_time(end).1 + TimeDelta::minutes(15);
+                format!("{day} {}\u{2013}{}", start_time.format("%-H:%M"), end_time.format("%-H:%M"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Adds a change to the summary of a member that is waiting in the scheduler webhook
+fn queue_digest(state: &'static UsrState, term: &term::Model, name: String, change: Change) {
+    let Some(scheduler_webhook) = &state.scheduler_webhook else {
+        return;
+    };
+    if matches!(&change, Change::Added(times) | Change::Removed(times) if times.is_empty())
+        || matches!(&change, Change::Teams(old, new) if old == new)
+    {
+        return;
+    }
+    let term_id = term.id;
+    let term_name = term.name.clone();
+    let id = {
+        let mut digests = state.scheduler_digests.lock();
+        let Digests { members, next_id } = &mut *digests;
+        members.entry((term_id, name.clone())).or_insert_with(|| {
+            *next_id = next_id.wrapping_add(1);
+            (*next_id, MemberChanges::default())
+        }).0
+    };
+
+    scheduler_webhook.enqueue_with(id, |queued| {
+        let mut digests = state.scheduler_digests.lock();
+        // The digest may have been sent and forgotten since the id was picked
+        let (_, changes) = digests.members.entry((term_id, name.clone())).or_insert_with(|| (id, MemberChanges::default()));
+        // The last digest was sent, so this starts a new one
+        if queued.is_none() {
+            *changes = MemberChanges::default();
+        }
+        changes.apply(change);
+        changes.summary(&name, &term_name)
+    });
+}
+
+#[derive(Serialize)]
+struct Schedule {
+    term: term::Model,
+    /// Everyone available in each slot, where there are `CLIENT_SLOTS_PER_WEEK`
+    /// slots if the client asked for a time zone
+    availabilities: Box<[Vec<String>]>,
+    /// Everyone in `availabilities` that is only available if needed
+    if_needed: Box<[Vec<String>]>,
+    teams: HashMap<String, Vec<String>>
+}
+
+/// Names of people in each slot
+type Slots = Box<[Vec<String>]>;
+
+fn empty_slots() -> Slots {
+    std::iter::from_fn(|| Some(Vec::default())).take(SLOTS_PER_WEEK as usize).collect()
+}
+
+/// Lists the names of everyone available in each slot, and everyone that is only available if needed
+fn weekly_slots(availabilities: Vec<availability::Model>, names: &HashMap<u32, String>) -> (Slots, Slots) {
+    let mut slots = empty_slots();
+    let mut if_needed = empty_slots();
+    for model in availabilities {
+        let Some(name) = names.get(&model.member) else {
+            continue;
+        };
+        if model.preference == Preference::IfNeeded {
+            if_needed[model.time as usize].push(name.clone());
+        }
+        slots[model.time as usize].push(name.clone());
+    }
+    (slots, if_needed)
+}
+
+/// Maps the names of teams that are not retired to the names of their members in a term
+async fn team_members(db: &impl ConnectionTrait, term: u32) -> Result<HashMap<String, Vec<String>>, sea_orm::DbErr> {
+    let (teams, team_infos, names) = tokio::join!(
+        team::Entity::find().filter(team::Column::Term.eq(term)).all(db),
+        team_info::Entity::find().filter(team_info::Column::Retired.eq(false)).all(db),
+        members::names(db),
+    );
+    let team_infos: HashMap<String, String> = team_infos?.into_iter().map(|model| (model.code, model.name)).collect();
+    let names = names?;
+
+    let mut out = HashMap::<String, Vec<String>>::new();
+    for model in teams? {
+        let (Some(team_name), Some(name)) = (team_infos.get(&model.team), names.get(&model.member)) else {
+            continue;
+        };
+        match out.entry(team_name.clone()) {
+            Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().push(name.clone()),
+            Entry::Vacant(vacant_entry) => vacant_entry.insert(vec![]).push(name.clone()),
+        }
+    }
+    Ok(out)
+}
+
+/// Maps the ids of members to the names of the teams they are on in the current term
+pub async fn member_teams(db: &impl ConnectionTrait, time_zone: Tz) -> Result<HashMap<u32, Vec<String>>, sea_orm::DbErr> {
+    let Some(term) = find_term(db, time_zone, None).await? else {
+        return Ok(HashMap::new());
+    };
+    let (teams, team_infos) = tokio::join!(
+        team::Entity::find().filter(team::Column::Term.eq(term.id)).all(db),
+        team_info::Entity::find().filter(team_info::Column::Retired.eq(false)).all(db),
+    );
+    let team_infos: HashMap<String, String> = team_infos?.into_iter().map(|model| (model.code, model.name)).collect();
+
+    let mut out = HashMap::<u32, Vec<String>>::new();
+    for model in teams? {
+        if let Some(team_name) = team_infos.get(&model.team) {
+            out.entry(model.member).or_default().push(team_name.clone());
+        }
+    }
+    Ok(out)
+}
+
+/// Renames a member in the invitees of every meeting
+pub async fn rename_member(db: &impl ConnectionTrait, old: &str, new: &str) -> Result<(), sea_orm::DbErr> {
+    for model in meeting::Entity::find().filter(meeting::Column::Invitees.contains(old)).all(db).await? {
+        let invitees = team_query::rename(&model.invitees, old, new);
+        if invitees != model.invitees {
+            let active_model = meeting::ActiveModel {
+                id: ActiveValue::Unchanged(model.id),
+                invitees: ActiveValue::Set(invitees),
+                ..Default::default()
+            };
+            active_model.update(db).await?;
+        }
+    }
+    Ok(())
+}
+
+#[axum::debug_handler]
+async fn get_schedule(State(state): State<&'static UsrState>, Query(TermQuery { term, tz }): Query<TermQuery>) -> Response {
+    let zone = match client_zone(state, tz.as_deref(), today(state)) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let term = match find_term(&state.db, state.time_zone, term).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Term not found").into_response(),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let (availabilities, teams, names) = tokio::join!(
+        availability::Entity::find().filter(availability::Column::Term.eq(term.id)).all(&state.db),
+        team_members(&state.db, term.id),
+        members::names(&state.db),
+    );
+
+    let (availabilities, names) = match (availabilities, names) {
+        (Ok(availabilities), Ok(names)) => (availabilities, names),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to enumerate availabilities: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let teams = match teams {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to enumerate teams: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let (mut slots, mut if_needed) = weekly_slots(availabilities, &names);
+    if let Some(zone) = zone {
+        slots = zone.slots_to_client(slots);
+        if_needed = zone.slots_to_client(if_needed);
+    }
+
+    Json(Schedule {
+        term,
+        availabilities: slots,
+        if_needed,
+        teams,
+    }).into_response()
+}
+
+#[derive(Deserialize)]
+struct PendingOverride {
+    name: String,
+    date: Date,
+    /// Slots within `date`
+    times: Box<[u16]>,
+    /// Whether the slots are added to or removed from the weekly pattern
+    available: bool,
+    /// Time zone of `date` and `times`, which makes `times` count from 12 AM
+    #[serde(default)]
+    tz: Option<String>,
+}
+
+/// Matches overrides in any of the given slots
+fn override_slots(times: &[(Date, u16)]) -> Condition {
+    let mut condition = Condition::any();
+    for &(date, time) in times {
+        condition = condition.add(
+            Condition::all()
+                .add(availability_override::Column::Date.eq(date))
+                .add(availability_override::Column::Time.eq(time)),
+        );
+    }
+    condition
+}
+
+#[axum::debug_handler]
+async fn add_override(State(state): State<&'static UsrState>, user: User, Json(pending_override): Json<PendingOverride>) -> (StatusCode, &'static str) {
+    if pending_override.name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "");
+    }
+    let times = match client_zone(state, pending_override.tz.as_deref(), pending_override.date) {
+        Ok(zone) => match org_day_slots(zone.as_ref(), pending_override.date, &pending_override.times) {
+            Ok(x) => x,
+            Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+        },
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let member = members::find_or_create(tx, &pending_override.name).await?;
+        let before = availability_override::Entity::find()
+            .filter(availability_override::Column::Member.eq(member.id))
+            .filter(override_slots(&times))
+            .all(tx)
+            .await?;
+        let mut after = vec![];
+        for (date, time) in times {
+            let model = availability_override::Model {
+                member: member.id,
+                date,
+                time,
+                available: pending_override.available,
+            };
+            availability_override::Entity::insert(availability_override::ActiveModel::from(model.clone()))
+            .on_conflict(
+                OnConflict::columns([
+                    availability_override::Column::Member,
+                    availability_override::Column::Date,
+                    availability_override::Column::Time,
+                ])
+                .update_column(availability_override::Column::Available)
+                .to_owned(),
+            )
+            .exec(tx)
+            .await?;
+            after.push(model);
+        }
+        audit::record(tx, Some(user.id), "add_override", "availability_override", member.id, json!(before), json!(after)).await?;
+        Result::<_, sea_orm::DbErr>::Ok(())
+    })).await;
+
     if let Err(e) = result {
-        error!("Failed to delete schedule: {e}");
+        error!("Failed to insert override: {e}");
         (StatusCode::INTERNAL_SERVER_ERROR, "")
     } else {
         backup_db(state);
@@ -67,30 +819,52 @@ async fn del_schedule(State(state): State<&'static UsrState>, Json(pending_sched
 }
 
 #[derive(Deserialize)]
-struct SetTeam {
+struct DeleteOverride {
     name: String,
-    teams: HashSet<team::Team>,
+    date: Date,
+    times: Box<[u16]>,
+    #[serde(default)]
+    tz: Option<String>,
 }
 
+/// Removes overrides so that the slots follow the weekly pattern again
 #[axum::debug_handler]
-async fn set_teams(State(state): State<&'static UsrState>, Json(set_team): Json<SetTeam>) -> (StatusCode, &'static str) {
-    if set_team.name.is_empty() {
+async fn del_override(State(state): State<&'static UsrState>, user: User, Json(delete_override): Json<DeleteOverride>) -> (StatusCode, &'static str) {
+    if delete_override.name.is_empty() {
         return (StatusCode::BAD_REQUEST, "");
     }
-    let result = state.db.transaction(|tx| Box::pin(async move {
-        team::Entity::delete_many().filter(team::Column::Name.eq(set_team.name.clone())).exec(tx).await?;
-        for team in set_team.teams {
-            let active_model = team::ActiveModel {
-                name: ActiveValue::Set(set_team.name.clone()),
-                team: ActiveValue::Set(team)
-            };
-            active_model.insert(tx).await?;
+    let times = match client_zone(state, delete_override.tz.as_deref(), delete_override.date) {
+        Ok(zone) => match org_day_slots(zone.as_ref(), delete_override.date, &delete_override.times) {
+            Ok(x) => x,
+            Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+        },
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg),
+    };
+    let member = match members::find_by_name(&state.db, &delete_override.name).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::OK, ""),
+        Err(e) => {
+            error!("Failed to find member: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
         }
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let before = availability_override::Entity::find()
+            .filter(availability_override::Column::Member.eq(member.id))
+            .filter(override_slots(&times))
+            .all(tx)
+            .await?;
+        availability_override::Entity::delete_many()
+            .filter(availability_override::Column::Member.eq(member.id))
+            .filter(override_slots(&times))
+            .exec(tx)
+            .await?;
+        audit::record(tx, Some(user.id), "del_override", "availability_override", member.id, json!(before), Value::Null).await?;
         Result::<_, sea_orm::DbErr>::Ok(())
     })).await;
-    
+
     if let Err(e) = result {
-        error!("Failed to set teams: {e}");
+        error!("Failed to delete override: {e}");
         (StatusCode::INTERNAL_SERVER_ERROR, "")
     } else {
         backup_db(state);
@@ -98,23 +872,62 @@ async fn set_teams(State(state): State<&'static UsrState>, Json(set_team): Json<
     }
 }
 
+#[derive(Deserialize)]
+struct WeekQuery {
+    /// Any day in the week
+    date: Date,
+    /// Time zone to return times in, instead of the organization's
+    tz: Option<String>,
+}
+
 #[derive(Serialize)]
-struct Schedule {
-    availabilities: Box<[Vec<String>]>,
-    teams: HashMap<team::Team, Vec<String>>
+struct WeekSchedule {
+    /// The Monday that the week starts on
+    start: Date,
+    #[serde(flatten)]
+    schedule: Schedule,
 }
 
+/// Gets the schedule of a calendar week, which is the weekly pattern of the
+/// term in effect on `date` with the overrides of that week applied
 #[axum::debug_handler]
-async fn get_schedule(State(state): State<&'static UsrState>) -> Response {
-    let (availabilities, teams) = tokio::join!(
-        availability::Entity::find().all(&state.db),
-        team::Entity::find().all(&state.db),
+async fn get_week(State(state): State<&'static UsrState>, Query(WeekQuery { date, tz }): Query<WeekQuery>) -> Response {
+    let zone = match client_zone(state, tz.as_deref(), date) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let start = date - Days::new(date.weekday().num_days_from_monday() as u64);
+    let end = start + Days::new(6);
+
+    let term = match term_on(&state.db, date).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "No term had started").into_response(),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let (availabilities, overrides, teams, names) = tokio::join!(
+        availability::Entity::find().filter(availability::Column::Term.eq(term.id)).all(&state.db),
+        availability_override::Entity::find()
+            .filter(availability_override::Column::Date.between(start, end))
+            .all(&state.db),
+        team_members(&state.db, term.id),
+        members::names(&state.db),
     );
 
-    let availabilities = match availabilities {
+    let (availabilities, names) = match (availabilities, names) {
+        (Ok(availabilities), Ok(names)) => (availabilities, names),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to enumerate availabilities: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let overrides = match overrides {
         Ok(x) => x,
         Err(e) => {
-            error!("Failed to enumerate availabilities: {e}");
+            error!("Failed to enumerate overrides: {e}");
             return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
         }
     };
@@ -127,34 +940,1315 @@ async fn get_schedule(State(state): State<&'static UsrState>) -> Response {
         }
     };
 
-    Json(Schedule {
-        availabilities: {
-            let mut out: Box<[Vec<String>]> = std::iter::from_fn(|| Some(Vec::default())).take(7 * 10 * 4).collect();
-            for model in availabilities {
-                out[model.time as usize].push(model.name);
-            }
-            out
-        },
-        teams: {
-            let mut out = HashMap::<team::Team, Vec<String>>::new();
-            for model in teams {
-                match out.entry(model.team) {
-                    Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().push(model.name),
-                    Entry::Vacant(vacant_entry) => vacant_entry.insert(vec![]).push(model.name),
-                }
+    let (mut slots, mut if_needed) = weekly_slots(availabilities, &names);
+    // Slots added by overrides are preferred, since they were picked for that day
+    for model in overrides {
+        let Some(name) = names.get(&model.member) else {
+            continue;
+        };
+        let day = model.date.weekday().num_days_from_monday() as u16;
+        let slot = (day * SLOTS_PER_DAY + model.time) as usize;
+        if model.available {
+            if !slots[slot].contains(name) {
+                slots[slot].push(name.clone());
             }
-            out
+        } else {
+            slots[slot].retain(|other| other != name);
         }
+        if_needed[slot].retain(|other| other != name);
+    }
+    if let Some(zone) = zone {
+        slots = zone.slots_to_client(slots);
+        if_needed = zone.slots_to_client(if_needed);
+    }
+
+    Json(WeekSchedule {
+        start,
+        schedule: Schedule {
+            term,
+            availabilities: slots,
+            if_needed,
+            teams,
+        },
     }).into_response()
 }
 
+#[derive(Deserialize)]
+struct HeatmapQuery {
+    /// Defaults to the current term
+    term: Option<u32>,
+    /// Team query of who is counted, where an empty query counts everyone
+    #[serde(default)]
+    query: String,
+    /// Whether to also count each team separately
+    #[serde(default)]
+    split: bool,
+    /// Time zone to return times in, instead of the organization's
+    tz: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BestHour {
+    start: u16,
+    /// Sum of the preference weights of everyone available for the whole hour
+    score: u32,
+    available: u32,
+}
+
+#[derive(Serialize)]
+struct Heatmap {
+    term: term::Model,
+    /// How many people are available in each slot
+    counts: Box<[u32]>,
+    /// How many of the people in `counts` are only available if needed
+    if_needed: Box<[u32]>,
+    /// `counts` of each team, if they were asked for
+    teams: Option<HashMap<String, Box<[u32]>>>,
+    best_hours: Vec<BestHour>,
+    /// Slots where no member of any team is available
+    uncovered: Vec<u16>,
+    /// How many slots each team has at least one member available in
+    coverage: HashMap<String, u32>,
+}
+
+/// How many hours are in `best_hours`
+const BEST_HOUR_COUNT: usize = 5;
+
+/// Counts who is available in each slot, which is all that is needed to draw a heatmap
+#[axum::debug_handler]
+async fn get_heatmap(State(state): State<&'static UsrState>, Query(query): Query<HeatmapQuery>) -> Response {
+    let zone = match client_zone(state, query.tz.as_deref(), today(state)) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let term = match find_term(&state.db, state.time_zone, query.term).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Term not found").into_response(),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let (roster, teams) = tokio::join!(
+        Roster::load(&state.db, term.id),
+        team_members(&state.db, term.id),
+    );
+    let (roster, teams) = match (roster, teams) {
+        (Ok(roster), Ok(teams)) => (roster, teams),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to load roster: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let counted = if query.query.is_empty() {
+        roster.names.clone()
+    } else {
+        match roster.parse(&query.query) {
+            Ok(x) => x.evaluate(&roster.teams, &roster.names),
+            Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        }
+    };
+
+    let mut counts = vec![0u32; SLOTS_PER_WEEK as usize].into_boxed_slice();
+    let mut if_needed = counts.clone();
+    for name in &counted {
+        for (&time, &preference) in roster.availabilities.get(name).into_iter().flatten() {
+            counts[time as usize] += 1;
+            if preference == Preference::IfNeeded {
+                if_needed[time as usize] += 1;
+            }
+        }
+    }
+
+    let mut team_counts = HashMap::with_capacity(teams.len());
+    let mut coverage = HashMap::with_capacity(teams.len());
+    let mut covered = vec![false; SLOTS_PER_WEEK as usize];
+    for (team, members) in teams {
+        let mut team_count = vec![0u32; SLOTS_PER_WEEK as usize].into_boxed_slice();
+        for name in members.iter().filter(|name| counted.contains(*name)) {
+            for &time in roster.availabilities.get(name).into_iter().flat_map(HashMap::keys) {
+                team_count[time as usize] += 1;
+                covered[time as usize] = true;
+            }
+        }
+        coverage.insert(team.clone(), team_count.iter().filter(|&&count| count > 0).count() as u32);
+        team_counts.insert(team, team_count);
+    }
+    let uncovered: Vec<u16> = (0..SLOTS_PER_WEEK).filter(|&time| !covered[time as usize]).collect();
+
+    let mut best_hours = vec![];
+    for start in (0..SLOTS_PER_WEEK).step_by(4) {
+        let mut best_hour = BestHour { start, score: 0, available: 0 };
+        for name in &counted {
+            if let Some(preference) = roster.preference(name, start..start + 4) {
+                best_hour.score += preference.weight();
+                best_hour.available += 1;
+            }
+        }
+        if best_hour.available > 0 {
+            best_hours.push(best_hour);
+        }
+    }
+    best_hours.sort_by(|a, b| b.score.cmp(&a.score).then(a.start.cmp(&b.start)));
+    best_hours.truncate(BEST_HOUR_COUNT);
+
+    let mut heatmap = Heatmap {
+        term,
+        counts,
+        if_needed,
+        teams: query.split.then_some(team_counts),
+        best_hours,
+        uncovered,
+        coverage,
+    };
+    if let Some(zone) = zone {
+        heatmap.counts = zone.slots_to_client(heatmap.counts);
+        heatmap.if_needed = zone.slots_to_client(heatmap.if_needed);
+        if let Some(teams) = &mut heatmap.teams {
+            for counts in teams.values_mut() {
+                *counts = zone.slots_to_client(std::mem::take(counts));
+            }
+        }
+        for best_hour in &mut heatmap.best_hours {
+            best_hour.start = zone.slot_to_client(best_hour.start);
+        }
+        for time in &mut heatmap.uncovered {
+            *time = zone.slot_to_client(*time);
+        }
+        heatmap.uncovered.sort();
+        heatmap.uncovered.dedup();
+    }
+
+    Json(heatmap).into_response()
+}
+
+#[derive(Serialize)]
+struct Terms {
+    current: Option<u32>,
+    terms: Vec<term::Model>,
+}
+
+#[axum::debug_handler]
+async fn list_terms(State(state): State<&'static UsrState>) -> Response {
+    let (terms, current) = tokio::join!(
+        term::Entity::find().order_by_asc(term::Column::Start).all(&state.db),
+        find_term(&state.db, state.time_zone, None),
+    );
+
+    match (terms, current) {
+        (Ok(terms), Ok(current)) => Json(Terms {
+            current: current.map(|model| model.id),
+            terms,
+        }).into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to list terms: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewTerm {
+    name: String,
+    start: Date,
+}
+
+#[axum::debug_handler]
+async fn new_term(State(state): State<&'static UsrState>, user: User, Json(new_term): Json<NewTerm>) -> (StatusCode, &'static str) {
+    if new_term.name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "");
+    }
+    match term::Entity::find()
+        .filter(
+            Condition::any()
+                .add(term::Column::Name.eq(&new_term.name))
+                .add(term::Column::Start.eq(new_term.start)),
+        )
+        .one(&state.db)
+        .await
+    {
+        Ok(Some(_)) => return (StatusCode::BAD_REQUEST, "A term with that name or start already exists"),
+        Ok(None) => {}
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    }
+
+    let active_model = term::ActiveModel {
+        id: ActiveValue::NotSet,
+        name: ActiveValue::Set(new_term.name),
+        start: ActiveValue::Set(new_term.start),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.insert(tx).await?;
+        audit::record(tx, Some(user.id), "new_term", "term", model.id, Value::Null, json!(model)).await
+    })).await;
+
+    if let Err(e) = result {
+        error!("Failed to create term: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "")
+    } else {
+        backup_db(state);
+        (StatusCode::OK, "")
+    }
+}
+
+#[derive(Deserialize)]
+struct CopyTeams {
+    /// Defaults to the current term
+    #[serde(default)]
+    from: Option<u32>,
+    to: u32,
+}
+
+/// Copies every team membership of one term into another, keeping any
+/// memberships that the other term already has
+#[axum::debug_handler]
+async fn copy_teams(State(state): State<&'static UsrState>, user: User, Json(copy_teams): Json<CopyTeams>) -> (StatusCode, &'static str) {
+    let from = match find_term(&state.db, state.time_zone, copy_teams.from).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Term not found"),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let to = match writable_term(&state.db, state.time_zone, Some(copy_teams.to)).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    if from.id == to.id {
+        return (StatusCode::BAD_REQUEST, "Cannot copy a term into itself");
+    }
+
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let memberships = team::Entity::find().filter(team::Column::Term.eq(from.id)).all(tx).await?;
+        let after = json!({ "from": from.id, "memberships": memberships.len() });
+        for model in memberships {
+            team::Entity::insert(team::ActiveModel {
+                term: ActiveValue::Set(to.id),
+                member: ActiveValue::Set(model.member),
+                team: ActiveValue::Set(model.team),
+            }).on_conflict_do_nothing().exec(tx).await?;
+        }
+        audit::record(tx, Some(user.id), "copy_teams", "term", to.id, Value::Null, after).await?;
+        Result::<_, sea_orm::DbErr>::Ok(())
+    })).await;
+
+    if let Err(e) = result {
+        error!("Failed to copy teams: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "")
+    } else {
+        backup_db(state);
+        (StatusCode::OK, "")
+    }
+}
+
+/// Everyone with availability or a team in a term, which is who team queries choose from
+struct Roster {
+    /// Slots of the weekly pattern that each person is available in
+    availabilities: HashMap<String, HashMap<u16, Preference>>,
+    /// Members of each team that is not retired, under both the name and the code of the team
+    teams: HashMap<String, HashSet<String>>,
+    names: HashSet<String>,
+}
+
+impl Roster {
+    async fn load(db: &impl ConnectionTrait, term: u32) -> Result<Self, sea_orm::DbErr> {
+        let (availabilities, memberships, team_infos, names) = tokio::join!(
+            availability::Entity::find().filter(availability::Column::Term.eq(term)).all(db),
+            team::Entity::find().filter(team::Column::Term.eq(term)).all(db),
+            team_info::Entity::find().filter(team_info::Column::Retired.eq(false)).all(db),
+            members::names(db),
+        );
+        let team_infos: HashMap<String, String> = team_infos?.into_iter().map(|model| (model.code, model.name)).collect();
+        let names = names?;
+        let mut roster = Self {
+            availabilities: HashMap::new(),
+            teams: HashMap::new(),
+            names: HashSet::new(),
+        };
+
+        for model in availabilities? {
+            let Some(name) = names.get(&model.member) else {
+                continue;
+            };
+            roster.names.insert(name.clone());
+            roster.availabilities.entry(name.clone()).or_default().insert(model.time, model.preference);
+        }
+        for model in memberships? {
+            let (Some(team_name), Some(name)) = (team_infos.get(&model.team), names.get(&model.member)) else {
+                continue;
+            };
+            roster.names.insert(name.clone());
+            roster.teams.entry(team_name.clone()).or_default().insert(name.clone());
+            roster.teams.entry(model.team).or_default().insert(name.clone());
+        }
+        for (code, name) in team_infos {
+            roster.teams.entry(name).or_default();
+            roster.teams.entry(code).or_default();
+        }
+
+        Ok(roster)
+    }
+
+    /// Parses a team query, making sure that every word in it is known
+    fn parse(&self, query: &str) -> Result<TeamQuery, String> {
+        let query = TeamQuery::parse(query)?;
+        if let Some(word) = query.unknown_word(&self.teams, &self.names) {
+            return Err(format!("Unknown name or team: {word}"));
+        }
+        Ok(query)
+    }
+
+    fn invited(&self, meeting: &meeting::Model) -> HashSet<String> {
+        let query = if meeting.invitees.is_empty() {
+            TeamQuery::Word(meeting.team.clone())
+        } else {
+            match TeamQuery::parse(&meeting.invitees) {
+                Ok(x) => x,
+                Err(_) => return HashSet::new(),
+            }
+        };
+        query.evaluate(&self.teams, &self.names)
+    }
+
+    /// The least preferred level that someone is available at for all of the slots in `times`,
+    /// or `None` if they are not available for one of them
+    fn preference(&self, name: &str, times: std::ops::Range<u16>) -> Option<Preference> {
+        let available = self.availabilities.get(name)?;
+        let mut out = Preference::Preferred;
+        for time in times {
+            if *available.get(&time)? == Preference::IfNeeded {
+                out = Preference::IfNeeded;
+            }
+        }
+        Some(out)
+    }
+}
+
+const DAY_NAMES: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+/// Formats when a meeting happens, eg. "Tuesdays 10:00 AM - 11:00 AM"
+fn meeting_time(meeting: &meeting::Model) -> String {
+    let (day, start) = slot_time(meeting.start);
+    let end = start + TimeDelta::minutes(meeting.duration as i64 * 15);
+    format!(
+        "{}s {} - {}",
+        DAY_NAMES[day as usize],
+        start.format("%-I:%M %p"),
+        end.format("%-I:%M %p")
+    )
+}
+
+#[derive(Serialize)]
+struct MeetingInfo {
+    #[serde(flatten)]
+    meeting: meeting::Model,
+    invited: Vec<String>,
+    /// Invited people that are not available for the whole meeting
+    conflicts: Vec<String>,
+    /// Invited people that are only available for the meeting if needed
+    if_needed: Vec<String>,
+}
+
+impl MeetingInfo {
+    fn new(roster: &Roster, mut meeting: meeting::Model, team_names: &HashMap<String, String>) -> Self {
+        let mut invited: Vec<String> = roster.invited(&meeting).into_iter().collect();
+        invited.sort();
+        let mut conflicts = vec![];
+        let mut if_needed = vec![];
+        for name in &invited {
+            match roster.preference(name, meeting.start..meeting.start + meeting.duration) {
+                Some(Preference::Preferred) => {}
+                Some(Preference::IfNeeded) => if_needed.push(name.clone()),
+                None => conflicts.push(name.clone()),
+            }
+        }
+        // Meetings are stored with team codes, but clients know teams by name
+        if let Some(name) = team_names.get(&meeting.team) {
+            meeting.team.clone_from(name);
+        }
+        Self { meeting, invited, conflicts, if_needed }
+    }
+
+    fn webhook_message(&self, header: &str) -> String {
+        let mut msg = format!(
+            "**{header}**\n**Title:** {}\n**Team:** {}\n**When:** {}",
+            self.meeting.title,
+            self.meeting.team,
+            meeting_time(&self.meeting),
+        );
+        if !self.meeting.location.is_empty() {
+            msg.push_str(&format!("\n**Where:** {}", self.meeting.location));
+        }
+        if !self.meeting.invitees.is_empty() {
+            msg.push_str(&format!("\n**Invited:** {}", self.meeting.invitees));
+        }
+        if !self.conflicts.is_empty() {
+            msg.push_str(&format!("\n**Conflicts:** {}", self.conflicts.join(", ")));
+        }
+        msg
+    }
+}
+
+fn announce_meeting(state: &'static UsrState, team_code: &str, id: u32, msg: String) {
+    if let Some(team_webhook) = team_webhook(state, team_code) {
+        team_webhook.enqueue(id, msg.clone());
+    }
+    if let Some(meetings_webhook) = &state.meetings_webhook {
+        meetings_webhook.enqueue(id, msg);
+    }
+}
+
+#[derive(Deserialize)]
+struct PendingMeeting {
+    /// Only used when changing a meeting
+    #[serde(default)]
+    id: u32,
+    title: String,
+    team: String,
+    start: u16,
+    duration: u16,
+    #[serde(default)]
+    location: String,
+    /// Team query of who is invited, where an empty query invites the whole team
+    #[serde(default)]
+    invitees: String,
+    /// Only used when creating a meeting, and defaults to the current term
+    #[serde(default)]
+    term: Option<u32>,
+    /// Time zone of `start`, which makes it count from 12 AM Monday in that time zone
+    #[serde(default)]
+    tz: Option<String>,
+}
+
+impl PendingMeeting {
+    /// Moves `start` into the organization's time zone, returning the client's time zone
+    fn move_to_org(&mut self, state: &'static UsrState) -> Result<Option<Zone>, &'static str> {
+        let zone = client_zone(state, self.tz.as_deref(), today(state))?;
+        if let Some(zone) = &zone {
+            self.start = zone.slot_from_client(self.start).ok_or("Meeting is outside of the day")?;
+        }
+        Ok(zone)
+    }
+}
+
+/// Checks a pending meeting, returning the meeting that would be stored and the roster of its term
+async fn check_meeting(db: &impl ConnectionTrait, pending_meeting: PendingMeeting, term: u32) -> Result<Result<(meeting::Model, Roster), String>, sea_orm::DbErr> {
+    if pending_meeting.title.is_empty() || pending_meeting.duration == 0 {
+        return Ok(Err(String::new()));
+    }
+    if pending_meeting.start >= SLOTS_PER_WEEK || pending_meeting.start % SLOTS_PER_DAY + pending_meeting.duration > SLOTS_PER_DAY {
+        return Ok(Err("Meeting is outside of the day".into()));
+    }
+    let Some(team) = find_team(db, &pending_meeting.team).await? else {
+        return Ok(Err("Unknown team".into()));
+    };
+    let roster = Roster::load(db, term).await?;
+    if !pending_meeting.invitees.is_empty() {
+        if let Err(e) = roster.parse(&pending_meeting.invitees) {
+            return Ok(Err(e));
+        }
+    }
+
+    Ok(Ok((
+        meeting::Model {
+            id: pending_meeting.id,
+            term,
+            title: pending_meeting.title,
+            team: team.code,
+            start: pending_meeting.start,
+            duration: pending_meeting.duration,
+            location: pending_meeting.location,
+            invitees: pending_meeting.invitees,
+        },
+        roster,
+    )))
+}
+
+#[axum::debug_handler]
+async fn new_meeting(State(state): State<&'static UsrState>, user: User, Json(mut pending_meeting): Json<PendingMeeting>) -> Response {
+    let zone = match pending_meeting.move_to_org(state) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let term = match writable_term(&state.db, state.time_zone, pending_meeting.term).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let (model, roster) = match check_meeting(&state.db, pending_meeting, term.id).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to check meeting: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut active_model: meeting::ActiveModel = model.into();
+    active_model.id = ActiveValue::NotSet;
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.insert(tx).await?;
+        audit::record(tx, Some(user.id), "new_meeting", "meeting", model.id, Value::Null, json!(model)).await?;
+        Result::<_, sea_orm::DbErr>::Ok(model)
+    })).await;
+    let (model, team_names) = match result {
+        Ok(model) => match team_names(&state.db).await {
+            Ok(team_names) => (model, team_names),
+            Err(e) => {
+                error!("Failed to get team names: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        },
+        Err(e) => {
+            error!("Failed to create meeting: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    backup_db(state);
+    let team_code = model.team.clone();
+    let mut info = MeetingInfo::new(&roster, model, &team_names);
+    announce_meeting(state, &team_code, info.meeting.id, info.webhook_message("New Meeting!"));
+    if let Some(zone) = zone {
+        info.meeting.start = zone.slot_to_client(info.meeting.start);
+    }
+    Json(info).into_response()
+}
+
+#[axum::debug_handler]
+async fn change_meeting(State(state): State<&'static UsrState>, user: User, Json(mut pending_meeting): Json<PendingMeeting>) -> Response {
+    let zone = match pending_meeting.move_to_org(state) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let old_model = match meeting::Entity::find_by_id(pending_meeting.id).one(&state.db).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Meeting not found").into_response(),
+        Err(e) => {
+            error!("Failed to find meeting: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    match writable_term(&state.db, state.time_zone, Some(old_model.term)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    }
+    let (model, roster) = match check_meeting(&state.db, pending_meeting, old_model.term).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+        Err(e) => {
+            error!("Failed to check meeting: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let active_model = meeting::ActiveModel {
+        id: ActiveValue::Unchanged(model.id),
+        term: ActiveValue::Unchanged(model.term),
+        title: ActiveValue::Set(model.title),
+        team: ActiveValue::Set(model.team),
+        start: ActiveValue::Set(model.start),
+        duration: ActiveValue::Set(model.duration),
+        location: ActiveValue::Set(model.location),
+        invitees: ActiveValue::Set(model.invitees),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.update(tx).await?;
+        audit::record(tx, Some(user.id), "change_meeting", "meeting", model.id, json!(old_model), json!(model)).await?;
+        Result::<_, sea_orm::DbErr>::Ok(model)
+    })).await;
+    let (model, team_names) = match result {
+        Ok(model) => match team_names(&state.db).await {
+            Ok(team_names) => (model, team_names),
+            Err(e) => {
+                error!("Failed to get team names: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        },
+        Err(e) => {
+            error!("Failed to change meeting: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    backup_db(state);
+    let team_code = model.team.clone();
+    let mut info = MeetingInfo::new(&roster, model, &team_names);
+    announce_meeting(state, &team_code, info.meeting.id, info.webhook_message("Meeting Changed"));
+    if let Some(zone) = zone {
+        info.meeting.start = zone.slot_to_client(info.meeting.start);
+    }
+    Json(info).into_response()
+}
+
+#[derive(Deserialize)]
+struct DeleteMeeting {
+    id: u32,
+}
+
+#[axum::debug_handler]
+async fn del_meeting(State(state): State<&'static UsrState>, user: User, Json(DeleteMeeting { id }): Json<DeleteMeeting>) -> (StatusCode, &'static str) {
+    let model = match meeting::Entity::find_by_id(id).one(&state.db).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Meeting not found"),
+        Err(e) => {
+            error!("Failed to find meeting: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    match writable_term(&state.db, state.time_zone, Some(model.term)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(msg)) => return (StatusCode::BAD_REQUEST, msg),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    }
+    let team_name = match team_name(&state.db, &model.team).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to find team: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+
+    let before = json!(model);
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        meeting::Entity::delete_by_id(id).exec(tx).await?;
+        audit::record(tx, Some(user.id), "del_meeting", "meeting", id, before, Value::Null).await
+    })).await;
+
+    if let Err(e) = result {
+        error!("Failed to delete meeting: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "")
+    } else {
+        backup_db(state);
+        announce_meeting(
+            state,
+            &model.team,
+            id,
+            format!(
+                "***Meeting Cancelled***\n**Title:** {}\n**Team:** {}\n**When:** {}",
+                model.title,
+                team_name,
+                meeting_time(&model),
+            ),
+        );
+        (StatusCode::OK, "")
+    }
+}
+
+#[axum::debug_handler]
+async fn list_meetings(State(state): State<&'static UsrState>, Query(TermQuery { term, tz }): Query<TermQuery>) -> Response {
+    let zone = match client_zone(state, tz.as_deref(), today(state)) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let term = match find_term(&state.db, state.time_zone, term).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Term not found").into_response(),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let (meetings, roster, team_names) = tokio::join!(
+        meeting::Entity::find()
+            .filter(meeting::Column::Term.eq(term.id))
+            .order_by_asc(meeting::Column::Start)
+            .all(&state.db),
+        Roster::load(&state.db, term.id),
+        team_names(&state.db),
+    );
+
+    match (meetings, roster, team_names) {
+        (Ok(meetings), Ok(roster), Ok(team_names)) => Json(
+            meetings
+                .into_iter()
+                .map(|meeting| {
+                    let mut info = MeetingInfo::new(&roster, meeting, &team_names);
+                    if let Some(zone) = &zone {
+                        info.meeting.start = zone.slot_to_client(info.meeting.start);
+                    }
+                    info
+                })
+                .collect::<Vec<_>>(),
+        ).into_response(),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            error!("Failed to list meetings: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SuggestQuery {
+    /// Team query of who should be at the meeting
+    invitees: String,
+    /// Length of the meeting in slots
+    duration: u16,
+    /// Defaults to the current term
+    #[serde(default)]
+    term: Option<u32>,
+    /// Time zone to return times in, instead of the organization's
+    tz: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Suggestion {
+    start: u16,
+    /// Sum of the preference weights of the invitees that can make it
+    score: u32,
+    conflicts: Vec<String>,
+    if_needed: Vec<String>,
+}
+
+/// How many suggestions are returned
+const SUGGESTION_COUNT: usize = 10;
+
+/// Ranks the times that a meeting could start at by who can make it, where people
+/// that prefer the time count for more than people that are only available if needed
+#[axum::debug_handler]
+async fn suggest_meeting(State(state): State<&'static UsrState>, Query(query): Query<SuggestQuery>) -> Response {
+    if query.duration == 0 || query.duration > SLOTS_PER_DAY {
+        return (StatusCode::BAD_REQUEST, "Meeting is outside of the day").into_response();
+    }
+    let zone = match client_zone(state, query.tz.as_deref(), today(state)) {
+        Ok(x) => x,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let term = match find_term(&state.db, state.time_zone, query.term).await {
+        Ok(Some(x)) => x,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Term not found").into_response(),
+        Err(e) => {
+            error!("Failed to find term: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let roster = match Roster::load(&state.db, term.id).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to load roster: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let invited = match roster.parse(&query.invitees) {
+        Ok(x) => x.evaluate(&roster.teams, &roster.names),
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let mut invited: Vec<String> = invited.into_iter().collect();
+    invited.sort();
+
+    let mut suggestions = vec![];
+    for start in 0..SLOTS_PER_WEEK {
+        if start % SLOTS_PER_DAY + query.duration > SLOTS_PER_DAY {
+            continue;
+        }
+        let mut suggestion = Suggestion {
+            start,
+            score: 0,
+            conflicts: vec![],
+            if_needed: vec![],
+        };
+        for name in &invited {
+            match roster.preference(name, start..start + query.duration) {
+                Some(preference) => {
+                    suggestion.score += preference.weight();
+                    if preference == Preference::IfNeeded {
+                        suggestion.if_needed.push(name.clone());
+                    }
+                }
+                None => suggestion.conflicts.push(name.clone()),
+            }
+        }
+        suggestions.push(suggestion);
+    }
+    suggestions.sort_by(|a, b| b.score.cmp(&a.score).then(a.start.cmp(&b.start)));
+    suggestions.truncate(SUGGESTION_COUNT);
+    if let Some(zone) = zone {
+        for suggestion in &mut suggestions {
+            suggestion.start = zone.slot_to_client(suggestion.start);
+        }
+    }
+
+    Json(suggestions).into_response()
+}
+
+/// Turns meetings into weekly events that repeat until the end of their term
+async fn meeting_events(state: &'static UsrState, meetings: Vec<meeting::Model>) -> Result<Vec<ical::Event>, sea_orm::DbErr> {
+    let (terms, team_names) = tokio::join!(
+        term::Entity::find().order_by_asc(term::Column::Start).all(&state.db),
+        team_names(&state.db),
+    );
+    let (terms, team_names) = (terms?, team_names?);
+    let mut events = Vec::with_capacity(meetings.len());
+
+    for meeting in meetings {
+        let Some(index) = terms.iter().position(|term| term.id == meeting.term) else {
+            continue;
+        };
+        let term = &terms[index];
+        let next_start = terms.get(index + 1).map(|term| term.start);
+        let (day, time) = slot_time(meeting.start);
+        let offset = (day as i64 - term.start.weekday().num_days_from_monday() as i64).rem_euclid(7);
+        let first = term.start + Days::new(offset as u64);
+        if next_start.is_some_and(|next_start| first >= next_start) {
+            continue;
+        }
+        let start = first.and_time(time);
+        let team_name = team_names.get(&meeting.team).cloned().unwrap_or(meeting.team);
+
+        events.push(ical::Event {
+            uid: format!("meeting-{}@usr-backend", meeting.id),
+            summary: meeting.title,
+            description: format!("{team_name} meeting"),
+            location: meeting.location,
+            start,
+            end: start + TimeDelta::minutes(meeting.duration as i64 * 15),
+            until: next_start.and_then(|next_start| {
+                state
+                    .time_zone
+                    .from_local_datetime(&next_start.and_time(NaiveTime::MIN))
+                    .earliest()
+                    .map(|until| until.with_timezone(&Utc))
+            }),
+        });
+    }
+
+    Ok(events)
+}
+
+fn calendar_response(name: &str, events: &[ical::Event], tz: Tz) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        ical::calendar(name, tz, events),
+    ).into_response()
+}
+
+/// Calendar of every meeting of a team, at `/ics/team/{team}.ics`
+#[axum::debug_handler]
+async fn team_calendar(State(state): State<&'static UsrState>, Path(file): Path<String>) -> Response {
+    let team = file.strip_suffix(".ics").unwrap_or(&file);
+    let team = match find_team(&state.db, team).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Unknown team").into_response(),
+        Err(e) => {
+            error!("Failed to find team: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+    let result = meeting::Entity::find()
+        .filter(meeting::Column::Team.eq(&team.code))
+        .all(&state.db)
+        .await;
+    let events = match result {
+        Ok(meetings) => meeting_events(state, meetings).await,
+        Err(e) => Err(e),
+    };
+
+    match events {
+        Ok(events) => calendar_response(&format!("{} Meetings", team.name), &events, state.time_zone),
+        Err(e) => {
+            error!("Failed to create team calendar: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+/// Calendar of every meeting that a member was invited to, at `/ics/member/{name}.ics`
+#[axum::debug_handler]
+async fn member_calendar(State(state): State<&'static UsrState>, Path(file): Path<String>) -> Response {
+    let name = file.strip_suffix(".ics").unwrap_or(&file).to_string();
+    let result = async {
+        let mut rosters = HashMap::<u32, Roster>::new();
+        let mut invited = vec![];
+        for meeting in meeting::Entity::find().all(&state.db).await? {
+            let roster = match rosters.entry(meeting.term) {
+                Entry::Occupied(occupied_entry) => occupied_entry.into_mut(),
+                Entry::Vacant(vacant_entry) => vacant_entry.insert(Roster::load(&state.db, meeting.term).await?),
+            };
+            if roster.invited(&meeting).contains(&name) {
+                invited.push(meeting);
+            }
+        }
+        meeting_events(state, invited).await
+    }.await;
+
+    match result {
+        Ok(events) => calendar_response(&format!("{name}'s Meetings"), &events, state.time_zone),
+        Err(e) => {
+            error!("Failed to create member calendar: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewTeam {
+    code: String,
+    name: String,
+    color: String,
+    #[serde(default)]
+    webhook: Option<String>,
+    #[serde(default)]
+    required_hours: f64,
+}
+
+/// Checks that a team's required weekly hours fit in a week
+fn check_required_hours(hours: f64) -> Result<(), &'static str> {
+    if !(0.0..=168.0).contains(&hours) {
+        return Err("Required hours must be between 0 and 168");
+    }
+    Ok(())
+}
+
+#[axum::debug_handler]
+async fn new_team(State(state): State<&'static UsrState>, user: User, Json(new_team): Json<NewTeam>) -> (StatusCode, &'static str) {
+    if new_team.code.is_empty() || new_team.code.len() > 8 || !new_team.code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return (StatusCode::BAD_REQUEST, "Team code must be 1 to 8 letters or digits");
+    }
+    if new_team.name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "");
+    }
+    if new_team.color.len() != 7 || !new_team.color.starts_with('#') || !new_team.color[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+        return (StatusCode::BAD_REQUEST, "Color must be of the form #rrggbb");
+    }
+    let webhook = match &new_team.webhook {
+        Some(url) => match DiscordWebhook::new(url) {
+            Ok(x) => Some(x),
+            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid webhook"),
+        },
+        None => None,
+    };
+    if let Err(msg) = check_required_hours(new_team.required_hours) {
+        return (StatusCode::BAD_REQUEST, msg);
+    }
+
+    let existing = match team_info::Entity::find()
+        .filter(
+            Condition::any()
+                .add(team_info::Column::Code.eq(&new_team.code))
+                .add(team_info::Column::Name.eq(&new_team.name)),
+        )
+        .all(&state.db)
+        .await
+    {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to find team: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let mut revived = None;
+    for model in existing {
+        if model.code != new_team.code {
+            return (StatusCode::BAD_REQUEST, "Team name is already taken");
+        }
+        if !model.retired {
+            return (StatusCode::BAD_REQUEST, "Team already exists");
+        }
+        // Creating a retired team brings it back
+        revived = Some(model);
+    }
+
+    let active_model = team_info::ActiveModel {
+        code: ActiveValue::Set(new_team.code.clone()),
+        name: ActiveValue::Set(new_team.name),
+        color: ActiveValue::Set(new_team.color),
+        webhook: ActiveValue::Set(new_team.webhook),
+        retired: ActiveValue::Set(false),
+        required_hours: ActiveValue::Set(new_team.required_hours),
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = if revived.is_some() {
+            active_model.update(tx).await?
+        } else {
+            active_model.insert(tx).await?
+        };
+        audit::record(tx, Some(user.id), "new_team", "team", &model.code, json!(revived), json!(model)).await
+    })).await;
+
+    if let Err(e) = result {
+        error!("Failed to create team: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "")
+    } else {
+        if let Some(webhook) = webhook {
+            state.team_webhooks.write().insert(new_team.code, Arc::new(webhook.into()));
+        }
+        backup_db(state);
+        (StatusCode::OK, "")
+    }
+}
+
+#[derive(Deserialize)]
+struct RetireTeam {
+    code: String,
+}
+
+#[axum::debug_handler]
+async fn retire_team(State(state): State<&'static UsrState>, user: User, Json(RetireTeam { code }): Json<RetireTeam>) -> (StatusCode, &'static str) {
+    let before = match team_info::Entity::find_by_id(&code).one(&state.db).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Team not found"),
+        Err(e) => {
+            error!("Failed to find team: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let active_model = team_info::ActiveModel {
+        code: ActiveValue::Unchanged(code.clone()),
+        name: ActiveValue::NotSet,
+        color: ActiveValue::NotSet,
+        webhook: ActiveValue::NotSet,
+        retired: ActiveValue::Set(true),
+        required_hours: ActiveValue::NotSet,
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let model = active_model.update(tx).await?;
+        audit::record(tx, Some(user.id), "retire_team", "team", &model.code, json!(before), json!(model)).await
+    })).await;
+
+    match result {
+        Ok(_) => {
+            state.team_webhooks.write().remove(&code);
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to retire team: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SetRequirement {
+    /// Code or name of the team
+    team: String,
+    hours: f64,
+}
+
+/// Sets the hours that each member of a team must spend in the lab every week
+#[axum::debug_handler]
+async fn set_requirement(State(state): State<&'static UsrState>, user: User, Json(SetRequirement { team, hours }): Json<SetRequirement>) -> (StatusCode, &'static str) {
+    if let Err(msg) = check_required_hours(hours) {
+        return (StatusCode::BAD_REQUEST, msg);
+    }
+    let model = match find_team(&state.db, &team).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Team not found"),
+        Err(e) => {
+            error!("Failed to find team: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+    };
+    let active_model = team_info::ActiveModel {
+        code: ActiveValue::Unchanged(model.code.clone()),
+        required_hours: ActiveValue::Set(hours),
+        ..Default::default()
+    };
+    let result = state.db.transaction(|tx| Box::pin(async move {
+        let after = active_model.update(tx).await?;
+        audit::record(tx, Some(user.id), "set_requirement", "team", &after.code, json!(model), json!(after)).await
+    })).await;
+
+    match result {
+        Ok(_) => {
+            backup_db(state);
+            (StatusCode::OK, "")
+        }
+        Err(e) => {
+            error!("Failed to set required hours: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "")
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn list_teams(State(state): State<&'static UsrState>) -> Response {
+    match team_info::Entity::find().order_by_asc(team_info::Column::Name).all(&state.db).await {
+        Ok(teams) => Json(teams).into_response(),
+        Err(e) => {
+            error!("Failed to list teams: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+        }
+    }
+}
+
 pub fn router() -> Router<&'static UsrState> {
     Router::new()
     .route("/add/schedule", post(add_schedule))
     .route("/del/schedule", delete(del_schedule))
-    .route("/get/schedule", get(get_schedule))
+    .route("/add/override", post(add_override))
+    .route("/del/override", delete(del_override))
     .route("/set/team", post(set_teams))
+    .route("/copy/team", post(copy_teams))
+    .route("/new/meeting", post(new_meeting))
+    .route("/change/meeting", post(change_meeting))
+    .route("/del/meeting", delete(del_meeting))
+    .route("/new/term", post(new_term))
+    .route("/new/team", post(new_team))
+    .route("/del/team", delete(retire_team))
+    .route("/set/requirement", post(set_requirement))
+    .route_layer(middleware::from_fn_with_state(Scope::SchedulerWrite, accounts::require_user))
+    .route("/get/schedule", get(get_schedule))
+    .route("/get/week", get(get_week))
+    .route("/get/heatmap", get(get_heatmap))
+    .route("/list/term", get(list_terms))
+    .route("/list/meeting", get(list_meetings))
+    .route("/suggest/meeting", get(suggest_meeting))
+    .route("/ics/team/{file}", get(team_calendar))
+    .route("/ics/member/{file}", get(member_calendar))
+    .route("/list/team", get(list_teams))
     // .route("/get/team/:name", get(get_teams))
+    .route_layer(middleware::from_fn_with_state(Scope::SchedulerRead, accounts::require_scope))
+}
+
+/// Creates any missing tables and brings old rows up to date
+pub async fn init_tables(db: &DatabaseConnection, time_zone: Tz) -> Result<(), sea_orm::DbErr> {
+    let builder = db.get_database_backend();
+    let schema = Schema::new(builder);
+
+    db.execute(builder.build(schema.create_table_from_entity(team::Entity).if_not_exists())).await?;
+    db.execute(builder.build(schema.create_table_from_entity(availability::Entity).if_not_exists())).await?;
+    db.execute(builder.build(schema.create_table_from_entity(availability_override::Entity).if_not_exists())).await?;
+    db.execute(builder.build(schema.create_table_from_entity(team_info::Entity).if_not_exists())).await?;
+    db.execute(builder.build(schema.create_table_from_entity(term::Entity).if_not_exists())).await?;
+    db.execute(builder.build(schema.create_table_from_entity(meeting::Entity).if_not_exists())).await?;
+    migration::add_column(db, meeting::Entity, meeting::Column::Location).await?;
+    migration::add_column(db, meeting::Entity, meeting::Column::Invitees).await?;
+    migration::add_column(db, team_info::Entity, team_info::Column::RequiredHours).await?;
+
+    // There must always be a current term, so the first one starts with the earliest override or today.
+    // Only the date is selected, since old override tables have names instead of members until below
+    let today = Utc::now().with_timezone(&time_zone).date_naive();
+    let initial_start = availability_override::Entity::find()
+        .select_only()
+        .column(availability_override::Column::Date)
+        .order_by_asc(availability_override::Column::Date)
+        .into_tuple::<Date>()
+        .one(db)
+        .await?
+        .map_or(today, |date| date.min(today));
+    let terms = term::Entity::find().order_by_asc(term::Column::Start).all(db).await?;
+    let initial_term = match terms.first() {
+        // The initial term used to start at the epoch, which put its meetings in 1970
+        Some(model) if model.start == NaiveDate::default() => {
+            let start = match terms.get(1) {
+                Some(next) => initial_start.min(next.start - Days::new(1)),
+                None => initial_start,
+            };
+            term::ActiveModel {
+                id: ActiveValue::Unchanged(model.id),
+                start: ActiveValue::Set(start),
+                ..Default::default()
+            }.update(db).await?
+        }
+        Some(model) => model.clone(),
+        None => term::ActiveModel {
+            id: ActiveValue::NotSet,
+            name: ActiveValue::Set("Initial".into()),
+            start: ActiveValue::Set(initial_start),
+        }.insert(db).await?,
+    };
+
+    // Availabilities, teams and overrides used to refer to people by name instead of by member.
+    // Before that, availabilities and teams did not belong to any term and slots had no preference
+    if !migration::has_column(db, "availabilities", "member").await? {
+        let term = if migration::has_column(db, "availabilities", "term").await? {
+            "term".to_string()
+        } else {
+            initial_term.id.to_string()
+        };
+        let preference = if migration::has_column(db, "availabilities", "preference").await? {
+            "preference"
+        } else {
+            "'P'"
+        };
+        let overrides = migration::has_column(db, "availability_overrides", "name").await?;
+        let member = |table: &str| format!("(SELECT id FROM members WHERE members.name = \"{table}_old\".name)");
+
+        let tx = db.begin().await?;
+        let mut tables = vec!["availabilities", "teams"];
+        if overrides {
+            tables.push("availability_overrides");
+        }
+        for table in tables {
+            tx.execute_unprepared(&format!("INSERT OR IGNORE INTO members (name) SELECT DISTINCT name FROM \"{table}\""))
+                .await?;
+        }
+        migration::rebuild_table(
+            &tx,
+            availability::Entity,
+            "term, member, time, preference",
+            &format!("{term}, {}, time, {preference}", member("availabilities")),
+        ).await?;
+        migration::rebuild_table(
+            &tx,
+            team::Entity,
+            "term, member, team",
+            &format!("{term}, {}, team", member("teams")),
+        ).await?;
+        if overrides {
+            migration::rebuild_table(
+                &tx,
+                availability_override::Entity,
+                "member, date, time, available",
+                &format!("{}, date, time, available", member("availability_overrides")),
+            ).await?;
+        }
+        tx.commit().await?;
+    }
+
+    // Team codes used to be hard-coded, so seed them to keep old rows valid
+    if team_info::Entity::find().count(db).await? == 0 {
+        for (code, name, color) in LEGACY_TEAMS {
+            team_info::ActiveModel {
+                code: ActiveValue::Set(code.into()),
+                name: ActiveValue::Set(name.into()),
+                color: ActiveValue::Set(color.into()),
+                webhook: ActiveValue::Set(None),
+                retired: ActiveValue::Set(false),
+                required_hours: ActiveValue::Set(0.0),
+            }.insert(db).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the webhooks of every team that is not retired
+pub async fn load_team_webhooks(db: &DatabaseConnection) -> anyhow::Result<HashMap<String, Arc<BatchedWebhook>>> {
+    let mut out = HashMap::new();
+    let teams = team_info::Entity::find()
+        .filter(team_info::Column::Retired.eq(false))
+        .filter(team_info::Column::Webhook.is_not_null())
+        .all(db)
+        .await?;
+
+    for model in teams {
+        let Some(url) = model.webhook else {
+            continue;
+        };
+        out.insert(model.code, Arc::new(DiscordWebhook::new(url)?.into()));
+    }
+
+    Ok(out)
 }
 
 pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
@@ -163,8 +2257,14 @@ pub async fn reset_tables(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr>
 
     db.execute(builder.build(Table::drop().table(team::Entity).if_exists())).await?;
     db.execute(builder.build(Table::drop().table(availability::Entity).if_exists())).await?;
+    db.execute(builder.build(Table::drop().table(availability_override::Entity).if_exists())).await?;
+    db.execute(builder.build(Table::drop().table(term::Entity).if_exists())).await?;
+    db.execute(builder.build(Table::drop().table(meeting::Entity).if_exists())).await?;
     db.execute(builder.build(&schema.create_table_from_entity(team::Entity))).await?;
     db.execute(builder.build(&schema.create_table_from_entity(availability::Entity))).await?;
+    db.execute(builder.build(&schema.create_table_from_entity(availability_override::Entity))).await?;
+    db.execute(builder.build(&schema.create_table_from_entity(term::Entity))).await?;
+    db.execute(builder.build(&schema.create_table_from_entity(meeting::Entity))).await?;
 
     Ok(())
 }
\ No newline at end of file
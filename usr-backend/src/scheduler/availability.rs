@@ -1,18 +1,48 @@
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "availabilities")]
 pub struct Model {
+    /// Id of the term this row belongs to
     #[sea_orm(primary_key)]
-    pub name: String,
-    /// duration from 12 AM Monday, in 15 minute units
-    /// eg. 1 = 12:15 AM, 2 = 12:30 AM, 3 = 12:45 AM, 4 = 1:00 AM
+    pub term: u32,
+    /// Id of a row in `members`
     #[sea_orm(primary_key)]
-    pub time: u16
+    pub member: u32,
+    /// 15 minute slot in the week, counting from 9 AM Monday
+    /// Each day has `SLOTS_PER_DAY` slots, so 0 = 9:00 AM Monday,
+    /// 1 = 9:15 AM Monday, 40 = 9:00 AM Tuesday
+    #[sea_orm(primary_key)]
+    pub time: u16,
+    #[sea_orm(default_value = "P")]
+    pub preference: Preference,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
 }
 
-impl ActiveModelBehavior for ActiveModel {}
\ No newline at end of file
+impl ActiveModelBehavior for ActiveModel {}
+
+/// How much someone wants to meet in a slot they are available in
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Deserialize, Hash, Copy, Serialize, Default)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(1))")]
+pub enum Preference {
+    #[default]
+    #[sea_orm(string_value = "P")]
+    Preferred,
+    /// Available, but would rather not
+    #[sea_orm(string_value = "I")]
+    IfNeeded,
+}
+
+impl Preference {
+    /// How much a slot counts for when ranking slots
+    pub fn weight(self) -> u32 {
+        match self {
+            Preference::Preferred => 2,
+            Preference::IfNeeded => 1,
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "availability_overrides")]
+pub struct Model {
+    /// Id of a row in `members`
+    #[sea_orm(primary_key)]
+    pub member: u32,
+    #[sea_orm(primary_key)]
+    pub date: Date,
+    /// Slot within `date`, in the same units as `availability::Model::time`
+    /// eg. 0 = 9:00 AM, 1 = 9:15 AM
+    #[sea_orm(primary_key)]
+    pub time: u16,
+    /// Whether this adds the slot to the weekly pattern or removes it
+    pub available: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
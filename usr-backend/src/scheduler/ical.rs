@@ -0,0 +1,180 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Datelike, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+/// Years before today that the time zone covers. Older occurrences are rarely looked at,
+/// and every year covered is another year of days to scan for offset changes.
+const MAX_PAST_YEARS: i32 = 2;
+
+/// A weekly recurring event
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    pub description: String,
+    pub location: String,
+    /// Local time of the first occurrence
+    pub start: NaiveDateTime,
+    /// Local time that the first occurrence ends at
+    pub end: NaiveDateTime,
+    /// The event does not repeat after this
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Appends a content line, folding it so that no line is longer than 75 octets
+fn push_line(out: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ';' => out.push_str("\\;"),
+            ',' => out.push_str("\\,"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn format_local(time: NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%S").to_string()
+}
+
+fn format_utc(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// Writes a VTIMEZONE with every offset change of `tz` from the start of `from_year`
+/// to the end of `to_year`, so that clients without a time zone database still
+/// place events correctly across daylight saving changes
+fn push_timezone(out: &mut String, tz: Tz, from_year: i32, to_year: i32) {
+    let offset_at = |timestamp: i64| {
+        tz.offset_from_utc_datetime(&DateTime::from_timestamp(timestamp, 0).unwrap().naive_utc())
+    };
+    let mut transitions = vec![];
+    let mut timestamp = Utc.with_ymd_and_hms(from_year, 1, 1, 0, 0, 0).unwrap().timestamp();
+    let end = Utc.with_ymd_and_hms(to_year + 1, 1, 1, 0, 0, 0).unwrap().timestamp();
+    let mut offset = offset_at(timestamp);
+
+    while timestamp < end {
+        let next = timestamp + 24 * 60 * 60;
+        let next_offset = offset_at(next);
+        if next_offset != offset {
+            // Offsets change at most once a day, so the change is somewhere in this day
+            let (mut lo, mut hi) = (timestamp, next);
+            while hi - lo > 1 {
+                let mid = lo + (hi - lo) / 2;
+                if offset_at(mid) == offset {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            transitions.push((hi, offset, next_offset));
+            offset = next_offset;
+        }
+        timestamp = next;
+    }
+
+    push_line(out, "BEGIN:VTIMEZONE");
+    push_line(out, &format!("TZID:{}", tz.name()));
+    if transitions.is_empty() {
+        push_line(out, "BEGIN:STANDARD");
+        push_line(out, "DTSTART:19700101T000000");
+        push_line(out, &format!("TZOFFSETFROM:{}", format_offset(offset.fix().local_minus_utc())));
+        push_line(out, &format!("TZOFFSETTO:{}", format_offset(offset.fix().local_minus_utc())));
+        if let Some(abbreviation) = offset.abbreviation() {
+            push_line(out, &format!("TZNAME:{abbreviation}"));
+        }
+        push_line(out, "END:STANDARD");
+    }
+    for (timestamp, from, to) in transitions {
+        let kind = if to.dst_offset().is_zero() { "STANDARD" } else { "DAYLIGHT" };
+        let from_seconds = from.fix().local_minus_utc();
+        // DTSTART is in the local time from before the change
+        let start = DateTime::from_timestamp(timestamp + from_seconds as i64, 0).unwrap().naive_utc();
+        push_line(out, &format!("BEGIN:{kind}"));
+        push_line(out, &format!("DTSTART:{}", format_local(start)));
+        push_line(out, &format!("TZOFFSETFROM:{}", format_offset(from_seconds)));
+        push_line(out, &format!("TZOFFSETTO:{}", format_offset(to.fix().local_minus_utc())));
+        if let Some(abbreviation) = to.abbreviation() {
+            push_line(out, &format!("TZNAME:{abbreviation}"));
+        }
+        push_line(out, &format!("END:{kind}"));
+    }
+    push_line(out, "END:VTIMEZONE");
+}
+
+/// Creates an iCalendar file where every event is in the local time of `tz`
+pub fn calendar(name: &str, tz: Tz, events: &[Event]) -> String {
+    let now = Utc::now();
+    let mut out = String::new();
+
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, "PRODID:-//Utah Student Robotics//usr-backend//EN");
+    push_line(&mut out, "CALSCALE:GREGORIAN");
+    push_line(&mut out, &format!("X-WR-CALNAME:{}", escape(name)));
+    push_line(&mut out, &format!("X-WR-TIMEZONE:{}", tz.name()));
+
+    let from_year = events
+        .iter()
+        .map(|event| event.start.year())
+        .min()
+        .unwrap_or(now.year())
+        .max(now.year() - MAX_PAST_YEARS);
+    // Events without an end repeat forever, so cover a few years past today
+    let to_year = events
+        .iter()
+        .filter_map(|event| event.until)
+        .chain(std::iter::once(now))
+        .map(|time| time.year())
+        .max()
+        .unwrap()
+        + 2;
+    push_timezone(&mut out, tz, from_year, to_year);
+
+    for event in events {
+        push_line(&mut out, "BEGIN:VEVENT");
+        push_line(&mut out, &format!("UID:{}", event.uid));
+        push_line(&mut out, &format!("DTSTAMP:{}", format_utc(now)));
+        push_line(&mut out, &format!("DTSTART;TZID={}:{}", tz.name(), format_local(event.start)));
+        push_line(&mut out, &format!("DTEND;TZID={}:{}", tz.name(), format_local(event.end)));
+        let mut rrule = String::from("RRULE:FREQ=WEEKLY");
+        if let Some(until) = event.until {
+            let _ = write!(rrule, ";UNTIL={}", format_utc(until));
+        }
+        push_line(&mut out, &rrule);
+        push_line(&mut out, &format!("SUMMARY:{}", escape(&event.summary)));
+        if !event.description.is_empty() {
+            push_line(&mut out, &format!("DESCRIPTION:{}", escape(&event.description)));
+        }
+        if !event.location.is_empty() {
+            push_line(&mut out, &format!("LOCATION:{}", escape(&event.location)));
+        }
+        push_line(&mut out, "END:VEVENT");
+    }
+
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "meetings")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: u32,
+    /// Id of the term the meeting repeats during
+    pub term: u32,
+    pub title: String,
+    /// Code of the team that the meeting is for
+    pub team: String,
+    /// First slot of the meeting, in the same units as `availability::Model::time`
+    pub start: u16,
+    /// Length of the meeting in slots
+    pub duration: u16,
+    #[sea_orm(default_value = "")]
+    pub location: String,
+    /// Team query of who is invited, where an empty query invites the whole team
+    #[sea_orm(default_value = "")]
+    pub invitees: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
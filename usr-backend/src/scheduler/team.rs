@@ -1,15 +1,17 @@
-use std::fmt::Display;
-
 use sea_orm::entity::prelude::*;
-use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "teams")]
 pub struct Model {
+    /// Id of the term this row belongs to
+    #[sea_orm(primary_key)]
+    pub term: u32,
+    /// Id of a row in `members`
     #[sea_orm(primary_key)]
-    pub name: String,
+    pub member: u32,
+    /// Code of a row in `team_info`
     #[sea_orm(primary_key)]
-    pub team: Team
+    pub team: String
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -17,26 +19,3 @@ pub enum Relation {
 }
 
 impl ActiveModelBehavior for ActiveModel {}
-
-#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum, Deserialize, Hash, Copy, Serialize)]
-#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(1))")]
-pub enum Team {
-    #[sea_orm(string_value = "C")]
-    Software,
-    #[sea_orm(string_value = "M")]
-    Mechanical,
-    #[sea_orm(string_value = "E")]
-    Electrical,
-    #[sea_orm(string_value = "S")]
-    Systems,
-    #[sea_orm(string_value = "G")]
-    Social,
-    #[sea_orm(string_value = "A")]
-    Admin,
-}
-
-impl Display for Team {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
\ No newline at end of file
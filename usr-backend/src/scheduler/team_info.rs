@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "team_info")]
+pub struct Model {
+    /// Short code stored in other tables, eg. "C" for Software
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub code: String,
+    #[sea_orm(unique)]
+    pub name: String,
+    /// CSS hex color, eg. "#ff0000"
+    pub color: String,
+    #[sea_orm(nullable)]
+    #[serde(skip)]
+    pub webhook: Option<String>,
+    /// Retired teams are kept so that old orders still resolve,
+    /// but cannot be assigned to anything new
+    pub retired: bool,
+    /// Hours that each member must spend in the lab every week, where 0 is no requirement
+    #[sea_orm(default_value = 0.0)]
+    pub required_hours: f64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,273 @@
+use std::collections::{HashMap, HashSet};
+
+/// A query over teams and people, such as `(Software or Mechanical) and !Naj`.
+///
+/// This follows the same rules as `TeamQuery` in the web app: operators are
+/// applied from left to right, `*` is everyone, `!` removes people from everyone,
+/// and the name of a person is added to whatever it is combined with.
+#[derive(Debug, Clone)]
+pub enum TeamQuery {
+    Word(String),
+    And(Box<TeamQuery>, Box<TeamQuery>),
+    Or(Box<TeamQuery>, Box<TeamQuery>),
+}
+
+enum Operand {
+    Set(HashSet<String>),
+    Name(String),
+}
+
+/// Replaces a name in a query with another name, keeping the rest of the query as it is
+pub fn rename(query: &str, old: &str, new: &str) -> String {
+    let mut out = String::with_capacity(query.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, out: &mut String| {
+        let name = word.trim_start_matches('!');
+        if name == old {
+            out.push_str(&word[..word.len() - name.len()]);
+            out.push_str(new);
+        } else {
+            out.push_str(word);
+        }
+        word.clear();
+    };
+    for c in query.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            flush(&mut word, &mut out);
+            out.push(c);
+        } else {
+            word.push(c);
+        }
+    }
+    flush(&mut word, &mut out);
+    out
+}
+
+impl TeamQuery {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let query = query.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens = vec![];
+        let mut negate = false;
+        for token in query.split_whitespace() {
+            if token.chars().all(|c| c == '!') {
+                negate = true;
+                continue;
+            }
+            if negate {
+                if token == "(" || token == ")" {
+                    return Err("'!' can only be used before a name".into());
+                }
+                tokens.push(format!("!{token}"));
+                negate = false;
+            } else {
+                tokens.push(token.to_string());
+            }
+        }
+        if negate {
+            return Err("'!' can only be used before a name".into());
+        }
+        if tokens.is_empty() {
+            return Err("Empty query".into());
+        }
+
+        let mut index = 0;
+        let query = Self::parse_tokens(&tokens, &mut index)?;
+        if index < tokens.len() {
+            return Err("Unmatched parentheses".into());
+        }
+        Ok(query)
+    }
+
+    fn parse_operand(tokens: &[String], index: &mut usize) -> Result<Self, String> {
+        let Some(token) = tokens.get(*index) else {
+            return Err("Invalid query".into());
+        };
+        *index += 1;
+        match token.as_str() {
+            "(" => {
+                let query = Self::parse_tokens(tokens, index)?;
+                if tokens.get(*index).map(String::as_str) != Some(")") {
+                    return Err("Unmatched parentheses".into());
+                }
+                *index += 1;
+                Ok(query)
+            }
+            ")" => Err("Unmatched parentheses".into()),
+            _ => Ok(Self::Word(token.clone())),
+        }
+    }
+
+    fn parse_tokens(tokens: &[String], index: &mut usize) -> Result<Self, String> {
+        let mut left = Self::parse_operand(tokens, index)?;
+        while let Some(operator) = tokens.get(*index) {
+            if operator == ")" {
+                break;
+            }
+            *index += 1;
+            let right = Self::parse_operand(tokens, index)?;
+            left = match operator.to_lowercase().as_str() {
+                "and" => Self::And(Box::new(left), Box::new(right)),
+                "or" => Self::Or(Box::new(left), Box::new(right)),
+                _ => return Err(format!("Invalid operator: {operator}")),
+            };
+        }
+        Ok(left)
+    }
+
+    /// Finds the first word that is neither `*`, a team, nor one of `names`
+    pub fn unknown_word(&self, teams: &HashMap<String, HashSet<String>>, names: &HashSet<String>) -> Option<&str> {
+        match self {
+            Self::Word(word) => {
+                let word = word.strip_prefix('!').unwrap_or(word);
+                if word == "*" || teams.contains_key(word) || names.contains(word) {
+                    None
+                } else {
+                    Some(word)
+                }
+            }
+            Self::And(left, right) | Self::Or(left, right) => left
+                .unknown_word(teams, names)
+                .or_else(|| right.unknown_word(teams, names)),
+        }
+    }
+
+    fn word_operand(word: &str, teams: &HashMap<String, HashSet<String>>, names: &HashSet<String>) -> Operand {
+        if let Some(word) = word.strip_prefix('!') {
+            match Self::word_operand(word, teams, names) {
+                Operand::Set(set) => Operand::Set(names.difference(&set).cloned().collect()),
+                Operand::Name(name) => {
+                    Operand::Set(names.iter().filter(|x| **x != name).cloned().collect())
+                }
+            }
+        } else if word == "*" {
+            Operand::Set(names.clone())
+        } else if let Some(members) = teams.get(word) {
+            Operand::Set(members.intersection(names).cloned().collect())
+        } else {
+            Operand::Name(word.to_string())
+        }
+    }
+
+    fn operand(&self, teams: &HashMap<String, HashSet<String>>, names: &HashSet<String>) -> Operand {
+        let (left, right, and) = match self {
+            Self::Word(word) => return Self::word_operand(word, teams, names),
+            Self::And(left, right) => (left, right, true),
+            Self::Or(left, right) => (left, right, false),
+        };
+        let left = left.operand(teams, names);
+        let right = right.operand(teams, names);
+
+        Operand::Set(match (left, right, and) {
+            (Operand::Set(left), Operand::Set(right), true) => left.intersection(&right).cloned().collect(),
+            (Operand::Set(left), Operand::Set(right), false) => left.union(&right).cloned().collect(),
+            (Operand::Name(left), Operand::Name(right), true) => {
+                if names.contains(&left) && names.contains(&right) {
+                    [left, right].into()
+                } else {
+                    HashSet::new()
+                }
+            }
+            (Operand::Name(left), Operand::Name(right), false) => {
+                [left, right].into_iter().filter(|name| names.contains(name)).collect()
+            }
+            (Operand::Name(name), Operand::Set(mut set), and) | (Operand::Set(mut set), Operand::Name(name), and) => {
+                if names.contains(&name) {
+                    set.insert(name);
+                    set
+                } else if and {
+                    HashSet::new()
+                } else {
+                    set
+                }
+            }
+        })
+    }
+
+    /// Finds everyone in `names` that matches the query, where `teams` maps teams to their members
+    pub fn evaluate(&self, teams: &HashMap<String, HashSet<String>>, names: &HashSet<String>) -> HashSet<String> {
+        match self.operand(teams, names) {
+            Operand::Set(set) => set,
+            Operand::Name(name) if names.contains(&name) => [name].into(),
+            Operand::Name(_) => HashSet::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn evaluate(query: &str) -> HashSet<String> {
+        let teams = HashMap::from([
+            ("Software".to_string(), set(&["Naj", "Ana", "Former"])),
+            ("Mechanical".to_string(), set(&["Ana", "Bo"])),
+        ]);
+        let names = set(&["Naj", "Ana", "Bo", "Cy"]);
+        TeamQuery::parse(query).unwrap().evaluate(&teams, &names)
+    }
+
+    #[test]
+    fn teams_and_people() {
+        assert_eq!(evaluate("*"), set(&["Naj", "Ana", "Bo", "Cy"]));
+        // Teams only include people in `names`
+        assert_eq!(evaluate("Software"), set(&["Naj", "Ana"]));
+        assert_eq!(evaluate("Software and Mechanical"), set(&["Ana"]));
+        assert_eq!(evaluate("Software OR Mechanical"), set(&["Naj", "Ana", "Bo"]));
+        assert_eq!(evaluate("!Software"), set(&["Bo", "Cy"]));
+        assert_eq!(evaluate("(Software or Mechanical) and !Naj"), set(&["Ana", "Bo"]));
+        assert_eq!(evaluate("* and ! Ana"), set(&["Naj", "Bo", "Cy"]));
+    }
+
+    #[test]
+    fn names_are_added() {
+        assert_eq!(evaluate("Cy"), set(&["Cy"]));
+        assert_eq!(evaluate("Mechanical and Cy"), set(&["Ana", "Bo", "Cy"]));
+        assert_eq!(evaluate("Naj or Cy"), set(&["Naj", "Cy"]));
+        assert_eq!(evaluate("Naj and Nobody"), set(&[]));
+        assert_eq!(evaluate("Mechanical or Nobody"), set(&["Ana", "Bo"]));
+        assert_eq!(evaluate("Nobody"), set(&[]));
+    }
+
+    #[test]
+    fn operators_apply_left_to_right() {
+        assert_eq!(evaluate("Software or Mechanical and !Ana"), set(&["Naj", "Bo"]));
+        assert_eq!(evaluate("Software or (Mechanical and !Ana)"), set(&["Naj", "Ana", "Bo"]));
+    }
+
+    #[test]
+    fn invalid_queries() {
+        for (query, error) in [
+            ("", "Empty query"),
+            ("  ", "Empty query"),
+            ("Software and", "Invalid query"),
+            ("(Software", "Unmatched parentheses"),
+            ("Software)", "Unmatched parentheses"),
+            ("()", "Unmatched parentheses"),
+            ("Naj !", "'!' can only be used before a name"),
+            ("!(Software)", "'!' can only be used before a name"),
+            ("Software xor Mechanical", "Invalid operator: xor"),
+        ] {
+            assert_eq!(TeamQuery::parse(query).unwrap_err(), error, "{query}");
+        }
+    }
+
+    #[test]
+    fn unknown_words() {
+        let teams = HashMap::from([("Software".to_string(), set(&["Naj"]))]);
+        let names = set(&["Naj"]);
+        let unknown = |query: &str| TeamQuery::parse(query).unwrap().unknown_word(&teams, &names).map(str::to_string);
+        assert_eq!(unknown("(Software or Naj) and !*"), None);
+        assert_eq!(unknown("Software and !Nobody"), Some("Nobody".into()));
+    }
+
+    #[test]
+    fn renames() {
+        assert_eq!(rename("(Naj or Ana) and !Naj", "Naj", "Najib"), "(Najib or Ana) and !Najib");
+        // Only whole names are replaced
+        assert_eq!(rename("Najib or  Naj", "Naj", "N"), "Najib or  N");
+    }
+}
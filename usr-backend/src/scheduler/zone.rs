@@ -0,0 +1,220 @@
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike};
+use chrono_tz::Tz;
+
+use super::{slot_time, DAY_START_HOUR, SLOTS_PER_DAY};
+
+/// Slots in a day of a client, which count from 12 AM instead of 9 AM
+pub const CLIENT_SLOTS_PER_DAY: u16 = 96;
+pub const CLIENT_SLOTS_PER_WEEK: u16 = CLIENT_SLOTS_PER_DAY * 7;
+
+/// Converts slots between the organization's time zone and a client's time zone.
+///
+/// A slot is only a time of the week, but the offset between two time zones changes
+/// with daylight saving time, so every conversion goes through the actual dates of one week.
+pub struct Zone {
+    org: Tz,
+    client: Tz,
+    /// The Monday of the week whose offsets are used
+    monday: NaiveDate,
+}
+
+impl Zone {
+    /// `date` can be any day in the week whose offsets are used
+    pub fn new(org: Tz, client: Tz, date: NaiveDate) -> Self {
+        Self {
+            org,
+            client,
+            monday: date - Days::new(date.weekday().num_days_from_monday() as u64),
+        }
+    }
+
+    /// Converts a local time from one time zone to another, picking the earlier
+    /// time when it happens twice and `None` when it is skipped
+    fn convert(from: Tz, to: Tz, datetime: NaiveDateTime) -> Option<NaiveDateTime> {
+        from.from_local_datetime(&datetime)
+            .earliest()
+            .map(|datetime| datetime.with_timezone(&to).naive_local())
+    }
+
+    /// Converts a slot within `date` in the organization's time zone to
+    /// the date and slot within that date in the client's time zone
+    fn day_to_client(&self, date: NaiveDate, slot: u16) -> (NaiveDate, u16) {
+        let (_, time) = slot_time(slot);
+        // Slots are during the day, so they are never skipped by daylight saving time
+        let datetime = Self::convert(self.org, self.client, date.and_time(time))
+            .unwrap_or(date.and_time(time));
+        (datetime.date(), quarter_hour(datetime.time()))
+    }
+
+    /// Converts a slot within `date` in the client's time zone to the date and
+    /// slot within that date in the organization's time zone, or `None` if the
+    /// time is outside of scheduling hours or skipped by daylight saving time
+    pub fn day_from_client(&self, date: NaiveDate, slot: u16) -> Option<(NaiveDate, u16)> {
+        let time = NaiveTime::from_hms_opt(slot as u32 / 4, slot as u32 % 4 * 15, 0)?;
+        let datetime = Self::convert(self.client, self.org, date.and_time(time))?;
+        let slot = quarter_hour(datetime.time()).checked_sub(DAY_START_HOUR as u16 * 4)?;
+        if slot >= SLOTS_PER_DAY || datetime.time().minute() % 15 != 0 {
+            return None;
+        }
+        Some((datetime.date(), slot))
+    }
+
+    /// Converts a slot of the week in the organization's time zone to a slot of the
+    /// week in the client's time zone, wrapping around the ends of the week
+    pub fn slot_to_client(&self, slot: u16) -> u16 {
+        let (day, _) = slot_time(slot);
+        let (date, slot) = self.day_to_client(self.monday + Days::new(day as u64), slot % SLOTS_PER_DAY);
+        let days = (date - self.monday).num_days();
+        (days * CLIENT_SLOTS_PER_DAY as i64 + slot as i64).rem_euclid(CLIENT_SLOTS_PER_WEEK as i64) as u16
+    }
+
+    /// Converts a slot of the week in the client's time zone to a slot of the week in
+    /// the organization's time zone, or `None` if it is outside of scheduling hours
+    pub fn slot_from_client(&self, slot: u16) -> Option<u16> {
+        if slot >= CLIENT_SLOTS_PER_WEEK {
+            return None;
+        }
+        let day = (slot / CLIENT_SLOTS_PER_DAY) as i64;
+        let slot = slot % CLIENT_SLOTS_PER_DAY;
+        // The ends of the client's week can wrap around to the other end of the organization's
+        // week, which has to be converted with the offsets of the week after or before
+        [0, 7, -7].into_iter().find_map(|shift| {
+            let (date, slot) = self.day_from_client(self.monday + TimeDelta::days(day + shift), slot)?;
+            let day = (date - self.monday).num_days();
+            (0..7).contains(&day).then(|| day as u16 * SLOTS_PER_DAY + slot)
+        })
+    }
+
+    /// Moves every slot of a week in the organization's time zone to the client's time zone.
+    /// Slots that land on the same client slot, such as when the client's clocks go back,
+    /// are merged.
+    pub fn slots_to_client<T: SlotValue>(&self, slots: Box<[T]>) -> Box<[T]> {
+        let mut out: Box<[T]> = std::iter::repeat_with(T::default).take(CLIENT_SLOTS_PER_WEEK as usize).collect();
+        for (slot, value) in slots.into_vec().into_iter().enumerate() {
+            out[self.slot_to_client(slot as u16) as usize].merge(value);
+        }
+        out
+    }
+}
+
+/// What a slot holds, which can be merged with what another slot holds
+pub trait SlotValue: Default {
+    fn merge(&mut self, other: Self);
+}
+
+/// Names of people, who are only listed once
+impl<T: PartialEq> SlotValue for Vec<T> {
+    fn merge(&mut self, other: Self) {
+        for value in other {
+            if !self.contains(&value) {
+                self.push(value);
+            }
+        }
+    }
+}
+
+/// Counts of people, who may be the same in both slots, so only the larger count is certain
+impl SlotValue for u32 {
+    fn merge(&mut self, other: Self) {
+        *self = (*self).max(other);
+    }
+}
+
+fn quarter_hour(time: NaiveTime) -> u16 {
+    (time.hour() * 4 + time.minute() / 15) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::{America, Asia, Pacific, UTC};
+
+    use super::*;
+    use crate::scheduler::SLOTS_PER_WEEK;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// Client slot of a time on a day, where 0 is Monday
+    fn client_slot(day: u16, hour: u16, minute: u16) -> u16 {
+        day * CLIENT_SLOTS_PER_DAY + hour * 4 + minute / 15
+    }
+
+    /// Organization slot of a time on a day, where 0 is Monday
+    fn org_slot(day: u16, hour: u16, minute: u16) -> u16 {
+        day * SLOTS_PER_DAY + (hour - DAY_START_HOUR as u16) * 4 + minute / 15
+    }
+
+    fn assert_round_trip(zone: &Zone) {
+        for slot in 0..SLOTS_PER_WEEK {
+            assert_eq!(zone.slot_from_client(zone.slot_to_client(slot)), Some(slot), "slot {slot}");
+        }
+    }
+
+    #[test]
+    fn spring_forward() {
+        // Denver moves to MDT on Sunday, March 8th 2026
+        let zone = Zone::new(America::Denver, UTC, date(2026, 3, 4));
+        assert_eq!(zone.slot_to_client(org_slot(0, 9, 0)), client_slot(0, 16, 0));
+        assert_eq!(zone.slot_to_client(org_slot(6, 9, 0)), client_slot(6, 15, 0));
+        assert_eq!(zone.slot_from_client(client_slot(6, 15, 0)), Some(org_slot(6, 9, 0)));
+        assert_eq!(zone.slot_from_client(client_slot(0, 15, 0)), None);
+        assert_round_trip(&zone);
+    }
+
+    #[test]
+    fn fall_back() {
+        // Denver moves to MST on Sunday, November 1st 2026
+        let zone = Zone::new(America::Denver, UTC, date(2026, 10, 26));
+        assert_eq!(zone.slot_to_client(org_slot(0, 9, 0)), client_slot(0, 15, 0));
+        assert_eq!(zone.slot_to_client(org_slot(6, 9, 0)), client_slot(6, 16, 0));
+        assert_eq!(zone.slot_from_client(client_slot(6, 16, 0)), Some(org_slot(6, 9, 0)));
+        assert_round_trip(&zone);
+    }
+
+    #[test]
+    fn client_ahead_of_utc() {
+        // Tokyo is a day ahead of Denver in the evening, so the end of Sunday wraps around to Monday
+        let zone = Zone::new(America::Denver, Asia::Tokyo, date(2026, 3, 2));
+        assert_eq!(zone.slot_to_client(org_slot(0, 9, 0)), client_slot(1, 1, 0));
+        assert_eq!(zone.slot_to_client(org_slot(6, 18, 45)), client_slot(0, 9, 45));
+        assert_eq!(zone.slot_from_client(client_slot(0, 9, 45)), Some(org_slot(6, 18, 45)));
+        assert_round_trip(&zone);
+
+        let zone = Zone::new(America::Denver, Asia::Tokyo, date(2026, 10, 26));
+        assert_round_trip(&zone);
+    }
+
+    #[test]
+    fn client_behind_org() {
+        // Monday morning in Denver is still Sunday in Honolulu, which wraps around to the end of the week
+        let zone = Zone::new(America::Denver, Pacific::Honolulu, date(2026, 3, 4));
+        assert_eq!(zone.slot_to_client(org_slot(0, 9, 0)), client_slot(0, 6, 0));
+        assert_round_trip(&zone);
+        let zone = Zone::new(America::Denver, Pacific::Kiritimati, date(2026, 3, 4));
+        assert_round_trip(&zone);
+    }
+
+    #[test]
+    fn colliding_slots_merge() {
+        // Auckland goes back from 3 AM to 2 AM on Sunday, April 5th 2026, which is
+        // 9 and 10 AM on Saturday in New York, so both hours land on 2 AM in Auckland
+        let zone = Zone::new(America::New_York, Pacific::Auckland, date(2026, 4, 1));
+        let first = org_slot(5, 9, 0);
+        let second = org_slot(5, 10, 0);
+        assert_eq!(zone.slot_to_client(first), client_slot(6, 2, 0));
+        assert_eq!(zone.slot_to_client(second), client_slot(6, 2, 0));
+
+        let mut names = vec![vec![]; SLOTS_PER_WEEK as usize].into_boxed_slice();
+        names[first as usize] = vec!["Naj", "Ana"];
+        names[second as usize] = vec!["Ana", "Ben"];
+        let names = zone.slots_to_client(names);
+        assert_eq!(names[client_slot(6, 2, 0) as usize], ["Naj", "Ana", "Ben"]);
+
+        let mut counts = vec![0u32; SLOTS_PER_WEEK as usize].into_boxed_slice();
+        counts[first as usize] = 2;
+        counts[second as usize] = 3;
+        let counts = zone.slots_to_client(counts);
+        assert_eq!(counts[client_slot(6, 2, 0) as usize], 3);
+    }
+}
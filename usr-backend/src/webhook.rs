@@ -1,4 +1,4 @@
-use std::{collections::HashMap, time::Instant};
+use std::{collections::HashMap, sync::{Arc, OnceLock}, time::Instant};
 
 use discord_webhook2::{message::Message, webhook::DiscordWebhook};
 use parking_lot::Mutex;
@@ -9,40 +9,63 @@ struct Locked {
     deadline: Option<Instant>,
 }
 
+type FlushCallback = Box<dyn Fn(&[u32]) + Send + Sync>;
+
 pub struct BatchedWebhook {
     locked: Mutex<Locked>,
     discord: DiscordWebhook,
+    /// Told the ids of every batch that is taken from the queue to be sent
+    on_flush: OnceLock<FlushCallback>,
 }
 
 impl BatchedWebhook {
-    pub fn enqueue(&'static self, id: u32, message: String) {
+    /// Calls `f` with the ids of every batch as it is taken from the queue to be sent, while
+    /// nothing else can be queued. This can only be set once.
+    pub fn on_flush(&self, f: impl Fn(&[u32]) + Send + Sync + 'static) {
+        if self.on_flush.set(Box::new(f)).is_err() {
+            error!("Webhook already has a flush callback");
+        }
+    }
+
+    pub fn enqueue(self: &Arc<Self>, id: u32, message: String) {
+        self.enqueue_with(id, |_| message);
+    }
+
+    /// Queues the message made by `f`, which is given the message still waiting
+    /// to be sent under the same id, or `None` if it was already sent
+    pub fn enqueue_with(self: &Arc<Self>, id: u32, f: impl FnOnce(Option<String>) -> String) {
         let mut guard = self.locked.lock();
-        guard.queue.insert(id, message);
+        let queued = guard.queue.remove(&id);
+        guard.queue.insert(id, f(queued));
         let was_none = guard.deadline.is_none();
         guard.deadline = Some(Instant::now() + std::time::Duration::from_secs(60 * 5));
 
         if was_none {
             drop(guard);
+            let this = self.clone();
             tokio::spawn(async move {
                 loop {
-                    let deadline = self.locked.lock().deadline.unwrap();
+                    let deadline = this.locked.lock().deadline.unwrap();
                     tokio::time::sleep_until(deadline.into()).await;
                     let queue;
                     {
-                        let mut guard = self.locked.lock();
+                        let mut guard = this.locked.lock();
                         if guard.deadline.unwrap() != deadline {
                             continue;
                         }
                         let replacement = HashMap::with_capacity(guard.queue.capacity());
                         queue = std::mem::replace(&mut guard.queue, replacement);
+                        if let Some(on_flush) = this.on_flush.get() {
+                            on_flush(&queue.keys().copied().collect::<Vec<_>>());
+                        }
                     }
                     let mut running = String::from(">>> ");
                     for (_, msg) in queue {
                         if running.len() + msg.len() + 1 < 2000 {
                             running.push_str(&msg);
-                            running.push_str("\n");
+                            running.push('\n');
                         } else {
-                            if let Err(e) = self.discord
+                            if let Err(e) = this.discord
                                 .send(&Message::new(|message| message.content(running)))
                                 .await
                             {
@@ -52,13 +75,13 @@ impl BatchedWebhook {
                             running.push_str(&msg);
                         }
                     }
-                    if let Err(e) = self.discord
+                    if let Err(e) = this.discord
                         .send(&Message::new(|message| message.content(running)))
                         .await
                     {
                         error!("Failed to trigger webhook: {e}");
                     }
-                    let mut guard = self.locked.lock();
+                    let mut guard = this.locked.lock();
                     if guard.queue.is_empty() {
                         guard.deadline = None;
                         break;
@@ -77,6 +100,7 @@ impl From<DiscordWebhook> for BatchedWebhook {
                 deadline: None,
             }),
             discord,
+            on_flush: OnceLock::new(),
         }
     }
 }
\ No newline at end of file